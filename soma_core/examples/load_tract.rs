@@ -1,16 +1,28 @@
-use soma_core::onnx_backend::{OnnxModel};
-use tract_onnx::prelude::*;
-use tract_core::model::typed::RunnableModel;
+use soma_core::onnx_backend::OnnxModel;
+use ndarray::ArrayD;
 use clap::Parser;
 #[derive(Parser)]
 struct CliArgs {
-    #[arg(long)]
-    input_image: String,
-    
     #[arg(long)]
     weights: String,
+
+    /// model input shape, e.g. `1,3,640,640`
+    #[arg(long, value_delimiter = ',', default_value = "1,3,640,640")]
+    input_shape: Vec<usize>,
 }
 
-pub fn main() {
+pub fn main() -> anyhow::Result<()> {
     let args = CliArgs::parse();
+    let load_time = std::time::Instant::now();
+    let model = OnnxModel::load(&args.weights, &args.input_shape, false)?;
+    println!("load time {:?}", load_time.elapsed());
 
+    let input = ArrayD::<f32>::zeros(args.input_shape.clone());
+    let run_time = std::time::Instant::now();
+    let outputs = model.run_f32(input.view())?;
+    println!("run time {:?}", run_time.elapsed());
+    for (idx, output) in outputs.iter().enumerate() {
+        println!("output {} shape {:?}", idx, output.shape());
+    }
+    Ok(())
+}
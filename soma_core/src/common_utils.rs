@@ -1,10 +1,20 @@
 use anyhow::{Error, Result};
-use image::DynamicImage;
+use image::{imageops, DynamicImage, Rgb, RgbImage};
 use ndarray::Array3;
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::cmp::{PartialEq, PartialOrd};
 
+/// model that produced a [Bbox]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Detector {
+    Yolo,
+    Retinaface,
+    #[default]
+    Unknown,
+}
+
 /// kpss = face keypoints
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, PartialOrd)]
 pub struct Bbox {
@@ -14,6 +24,8 @@ pub struct Bbox {
     pub y2: f32,
     pub confidence: f32,
     pub kpss: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub detector: Detector,
 }
 
 impl Bbox {
@@ -25,8 +37,15 @@ impl Bbox {
             y2,
             confidence,
             kpss,
+            detector: Detector::Unknown,
         }
     }
+
+    /// tags the bbox with the model that produced it
+    pub fn with_detector(mut self, detector: Detector) -> Bbox {
+        self.detector = detector;
+        self
+    }
     pub fn apply_image_scale(
         &mut self,
         original_image: &DynamicImage,
@@ -43,15 +62,61 @@ impl Bbox {
         let cart_y1 = original_image.height() as f32 * normalized_y1;
         let cart_y2 = original_image.height() as f32 * normalized_y2;
 
+        // keypoints are `[x, y, ..]`, anything after x/y (visibility etc.) is kept as is
+        let kpss = self
+            .kpss
+            .iter()
+            .map(|kp| {
+                let mut kp = kp.to_owned();
+                if kp.len() >= 2 {
+                    kp[0] = original_image.width() as f32 * (kp[0] / x_scale);
+                    kp[1] = original_image.height() as f32 * (kp[1] / y_scale);
+                }
+                kp
+            })
+            .collect();
+
         Bbox {
             x1: cart_x1,
             y1: cart_y1,
             x2: cart_x2,
             y2: cart_y2,
             confidence: self.confidence,
-            kpss: self.kpss.to_owned(),
+            kpss,
+            detector: self.detector,
+        }
+    }
+    /// maps a bbox (and its keypoints) from letterboxed model input coordinates
+    /// back to original image pixels, the box is clamped to the image
+    pub fn apply_letterbox(&self, letterbox: &Letterbox) -> Bbox {
+        let max_x = letterbox.original_width as f32;
+        let max_y = letterbox.original_height as f32;
+        let (x1, y1) = letterbox.to_original(self.x1, self.y1);
+        let (x2, y2) = letterbox.to_original(self.x2, self.y2);
+        let kpss = self
+            .kpss
+            .iter()
+            .map(|kp| {
+                let mut kp = kp.to_owned();
+                if kp.len() >= 2 {
+                    let (x, y) = letterbox.to_original(kp[0], kp[1]);
+                    kp[0] = x;
+                    kp[1] = y;
+                }
+                kp
+            })
+            .collect();
+        Bbox {
+            x1: x1.clamp(0.0, max_x),
+            y1: y1.clamp(0.0, max_y),
+            x2: x2.clamp(0.0, max_x),
+            y2: y2.clamp(0.0, max_y),
+            confidence: self.confidence,
+            kpss,
+            detector: self.detector,
         }
     }
+
     pub fn crop_bbox(&self, original_image: &DynamicImage) -> Result<DynamicImage, Error> {
         let bbox_width = (self.x2 - self.x1) as u32;
         let bbox_height = (self.y2 - self.y1) as u32;
@@ -64,6 +129,85 @@ impl Bbox {
     }
 }
 
+/// grey used to pad letterboxed images (same as ultralytics)
+pub const LETTERBOX_FILL: u8 = 114;
+
+/// where the resized image sits inside the model input, detectors expect
+/// the padding they were trained with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LetterboxPadding {
+    /// centred with [LETTERBOX_FILL] padding, yolov8
+    Centred,
+    /// top left with black padding, SCRFD (insightface `detect`)
+    TopLeft,
+}
+
+/// aspect preserving resize of an image into a `width` x `height` model input,
+/// padded as in [LetterboxPadding].
+///
+/// `scale_x` / `scale_y` are the effective scales after rounding the resized size,
+/// `pad_x` / `pad_y` are the offsets of the resized image inside the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Letterbox {
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub pad_x: f32,
+    pub pad_y: f32,
+    pub original_width: u32,
+    pub original_height: u32,
+}
+
+impl Letterbox {
+    /// maps a point from model input coordinates back to original image pixels
+    pub fn to_original(&self, x: f32, y: f32) -> (f32, f32) {
+        ((x - self.pad_x) / self.scale_x, (y - self.pad_y) / self.scale_y)
+    }
+
+    /// maps a point from original image pixels to model input coordinates
+    pub fn to_input(&self, x: f32, y: f32) -> (f32, f32) {
+        (x * self.scale_x + self.pad_x, y * self.scale_y + self.pad_y)
+    }
+}
+
+/// letterboxes `image` into a `width` x `height` rgb image,
+/// centred on [LETTERBOX_FILL] grey, see [Letterbox]
+pub fn letterbox(image: &DynamicImage, width: u32, height: u32) -> (DynamicImage, Letterbox) {
+    letterbox_with(image, width, height, LetterboxPadding::Centred)
+}
+
+/// [letterbox] with the padding of `padding`
+pub fn letterbox_with(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    padding: LetterboxPadding,
+) -> (DynamicImage, Letterbox) {
+    let (orig_w, orig_h) = (image.width().max(1), image.height().max(1));
+    let scale = (width as f32 / orig_w as f32).min(height as f32 / orig_h as f32);
+    let new_w = ((orig_w as f32 * scale).round() as u32).clamp(1, width);
+    let new_h = ((orig_h as f32 * scale).round() as u32).clamp(1, height);
+    let resized = image
+        .resize_exact(new_w, new_h, imageops::FilterType::Triangle)
+        .to_rgb8();
+    let (pad_x, pad_y, fill) = match padding {
+        LetterboxPadding::Centred => ((width - new_w) / 2, (height - new_h) / 2, LETTERBOX_FILL),
+        LetterboxPadding::TopLeft => (0, 0, 0),
+    };
+    let mut padded = RgbImage::from_pixel(width, height, Rgb([fill; 3]));
+    imageops::replace(&mut padded, &resized, pad_x as i64, pad_y as i64);
+    (
+        DynamicImage::ImageRgb8(padded),
+        Letterbox {
+            scale_x: new_w as f32 / orig_w as f32,
+            scale_y: new_h as f32 / orig_h as f32,
+            pad_x: pad_x as f32,
+            pad_y: pad_y as f32,
+            original_width: orig_w,
+            original_height: orig_h,
+        },
+    )
+}
+
 pub fn image_to_ndarray(img: &DynamicImage) -> Array3<f32> {
     let height = img.height();
     let width = img.width();
@@ -127,3 +271,110 @@ fn calculate_iou(box1: &Bbox, box2: &Bbox) -> f32 {
 
     intersection / union
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb<u8> = Rgb([200, 10, 50]);
+
+    /// a landscape and a portrait image, in original pixels
+    const SIZES: [(u32, u32); 2] = [(1280, 720), (480, 960)];
+
+    fn solid(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, RED))
+    }
+
+    fn assert_near(a: (f32, f32), b: (f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn to_input_and_to_original_round_trip() {
+        for (width, height) in SIZES {
+            for (input_w, input_h) in [(640, 640), (640, 384)] {
+                for padding in [LetterboxPadding::Centred, LetterboxPadding::TopLeft] {
+                    let (img, lb) =
+                        letterbox_with(&solid(width, height), input_w, input_h, padding);
+                    assert_eq!((img.width(), img.height()), (input_w, input_h));
+                    let (w, h) = (width as f32, height as f32);
+                    for point in [(0.0, 0.0), (w, h), (w / 3.0, h / 2.0), (w, 0.0)] {
+                        let (x, y) = lb.to_input(point.0, point.1);
+                        // the whole image lands inside the model input
+                        assert!((0.0..=input_w as f32).contains(&x), "{:?}", lb);
+                        assert!((0.0..=input_h as f32).contains(&y), "{:?}", lb);
+                        assert_near(lb.to_original(x, y), point);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn centred_padding_offsets() {
+        // 1280x720 -> 640x360, 140 rows above and below
+        let (img, lb) = letterbox(&solid(1280, 720), 640, 640);
+        assert_eq!((lb.pad_x, lb.pad_y), (0.0, 140.0));
+        assert_eq!((lb.scale_x, lb.scale_y), (0.5, 0.5));
+        let img = img.to_rgb8();
+        assert_eq!(*img.get_pixel(320, 139), Rgb([LETTERBOX_FILL; 3]));
+        assert_eq!(*img.get_pixel(320, 140), RED);
+        assert_eq!(*img.get_pixel(320, 499), RED);
+        assert_eq!(*img.get_pixel(320, 500), Rgb([LETTERBOX_FILL; 3]));
+        // 480x960 -> 320x640, 160 columns left and right
+        let (img, lb) = letterbox(&solid(480, 960), 640, 640);
+        assert_eq!((lb.pad_x, lb.pad_y), (160.0, 0.0));
+        let img = img.to_rgb8();
+        assert_eq!(*img.get_pixel(159, 320), Rgb([LETTERBOX_FILL; 3]));
+        assert_eq!(*img.get_pixel(160, 320), RED);
+        assert_eq!(*img.get_pixel(479, 320), RED);
+        assert_eq!(*img.get_pixel(480, 320), Rgb([LETTERBOX_FILL; 3]));
+    }
+
+    #[test]
+    fn top_left_padding_is_black() {
+        for (width, height) in SIZES {
+            let (img, lb) =
+                letterbox_with(&solid(width, height), 640, 640, LetterboxPadding::TopLeft);
+            assert_eq!((lb.pad_x, lb.pad_y), (0.0, 0.0));
+            let img = img.to_rgb8();
+            assert_eq!(*img.get_pixel(0, 0), RED);
+            assert_eq!(*img.get_pixel(639, 639), Rgb([0; 3]));
+        }
+    }
+
+    #[test]
+    fn apply_letterbox_maps_box_and_keypoints_back() {
+        for (width, height) in SIZES {
+            for padding in [LetterboxPadding::Centred, LetterboxPadding::TopLeft] {
+                let (_, lb) = letterbox_with(&solid(width, height), 640, 640, padding);
+                let original = Bbox::new(100.0, 50.0, 300.0, 250.0, 0.9, vec![vec![150.0, 120.0]]);
+                let (x1, y1) = lb.to_input(original.x1, original.y1);
+                let (x2, y2) = lb.to_input(original.x2, original.y2);
+                let (kx, ky) = lb.to_input(150.0, 120.0);
+                let mapped =
+                    Bbox::new(x1, y1, x2, y2, 0.9, vec![vec![kx, ky]]).apply_letterbox(&lb);
+                assert_near((mapped.x1, mapped.y1), (original.x1, original.y1));
+                assert_near((mapped.x2, mapped.y2), (original.x2, original.y2));
+                assert_near((mapped.kpss[0][0], mapped.kpss[0][1]), (150.0, 120.0));
+                assert_eq!(mapped.confidence, 0.9);
+            }
+        }
+    }
+
+    #[test]
+    fn apply_letterbox_clamps_to_the_image() {
+        // a box spilling over the whole model input, padding included
+        let (_, lb) = letterbox(&solid(1280, 720), 640, 640);
+        let mapped = Bbox::new(-10.0, 0.0, 650.0, 640.0, 0.9, vec![]).apply_letterbox(&lb);
+        assert_eq!(
+            (mapped.x1, mapped.y1, mapped.x2, mapped.y2),
+            (0.0, 0.0, 1280.0, 720.0)
+        );
+    }
+}
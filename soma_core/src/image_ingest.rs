@@ -0,0 +1,236 @@
+//! image ingestion shared by the services.
+//!
+//! every way a service receives an image (upload, raw body, base64, url)
+//! ends up as bytes that go through [decode_image], so size limits and
+//! format errors are the same everywhere. fetching urls is left to the
+//! services (they own the http client), [IngestConfig::allows_url_host]
+//! decides which hosts they may fetch from.
+//!
+//! decoded images are upright (exif orientation applied) and opaque
+//! (transparency flattened onto white). jpeg, png, webp, gif, bmp, tiff and
+//! friends decode out of the box, avif needs the `avif` feature (links `dav1d`),
+//! heic is not supported
+
+use base64::{engine::general_purpose, Engine as _};
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Cursor;
+
+/// default for [IngestConfig::max_bytes], 20 MiB
+pub const DEFAULT_MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// default for [IngestConfig::max_pixels], 50 megapixels
+pub const DEFAULT_MAX_IMAGE_PIXELS: u64 = 50_000_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    /// largest encoded image accepted, for every source
+    pub max_bytes: usize,
+    /// largest decoded image accepted (width * height), checked from the
+    /// header before decoding so small files can't expand into huge buffers
+    pub max_pixels: u64,
+    /// largest width or height accepted
+    pub max_side: u32,
+    /// hosts images may be fetched from, `example.com` also allows its subdomains.
+    /// empty disables url inputs
+    pub url_allowlist: Vec<String>,
+    /// timeout for the whole url fetch
+    pub url_timeout_secs: u64,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        IngestConfig {
+            max_bytes: DEFAULT_MAX_IMAGE_BYTES,
+            max_pixels: DEFAULT_MAX_IMAGE_PIXELS,
+            max_side: 16384,
+            url_allowlist: vec![],
+            url_timeout_secs: 10,
+        }
+    }
+}
+
+impl IngestConfig {
+    /// true if `host` or one of its parent domains is in the allowlist
+    pub fn allows_url_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.url_allowlist.iter().any(|allowed| {
+            let allowed = allowed.trim_end_matches('.').to_lowercase();
+            host == allowed || host.ends_with(&format!(".{}", allowed))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum IngestError {
+    /// no bytes at all
+    Empty,
+    /// over [IngestConfig::max_bytes]
+    TooLarge { limit: usize },
+    /// over [IngestConfig::max_pixels] or [IngestConfig::max_side]
+    TooManyPixels { width: u32, height: u32 },
+    /// the base64 payload does not decode
+    InvalidBase64(String),
+    /// not an image format `image` can decode
+    UnsupportedFormat(String),
+    /// looks like a supported image but cannot be decoded
+    Corrupt(String),
+    /// url inputs are disabled or the host is not allowlisted
+    UrlNotAllowed(String),
+    /// the url could not be fetched
+    Fetch(String),
+}
+
+impl fmt::Display for IngestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IngestError::Empty => write!(f, "the image is empty"),
+            IngestError::TooLarge { limit } => {
+                write!(f, "the image is larger than the {} bytes limit", limit)
+            }
+            IngestError::TooManyPixels { width, height } => write!(
+                f,
+                "the image is {}x{}, which is over the dimension limits",
+                width, height
+            ),
+            IngestError::InvalidBase64(reason) => write!(f, "invalid base64 image: {}", reason),
+            IngestError::UnsupportedFormat(reason) => {
+                write!(f, "not a supported image format: {}", reason)
+            }
+            IngestError::Corrupt(reason) => write!(f, "the image cannot be decoded: {}", reason),
+            IngestError::UrlNotAllowed(url) => {
+                write!(f, "fetching images from `{}` is not allowed", url)
+            }
+            IngestError::Fetch(reason) => write!(f, "cannot fetch the image: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for IngestError {}
+
+impl From<ImageError> for IngestError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::Unsupported(e) => IngestError::UnsupportedFormat(e.to_string()),
+            // the bytes are already in memory, io errors here mean a truncated file
+            e => IngestError::Corrupt(e.to_string()),
+        }
+    }
+}
+
+/// checks the size limit, guesses the format from the content, checks the
+/// dimensions from the header and decodes, see the module docs for the output
+pub fn decode_image(bytes: &[u8], config: &IngestConfig) -> Result<DynamicImage, IngestError> {
+    if bytes.is_empty() {
+        return Err(IngestError::Empty);
+    }
+    if bytes.len() > config.max_bytes {
+        return Err(IngestError::TooLarge {
+            limit: config.max_bytes,
+        });
+    }
+    if is_heic(bytes) {
+        return Err(IngestError::UnsupportedFormat(String::from(
+            "heic / heif images are not supported, send jpeg, png or webp",
+        )));
+    }
+    // the reader keeps the `image` default allocation limit as a backstop
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| IngestError::Corrupt(e.to_string()))?;
+    // the jpeg decoder fills a cut off scan with gray instead of failing
+    if reader.format() == Some(ImageFormat::Jpeg) && is_truncated_jpeg(bytes) {
+        return Err(IngestError::Corrupt(String::from(
+            "the jpeg ends before its end of image marker",
+        )));
+    }
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    if width > config.max_side
+        || height > config.max_side
+        || width as u64 * height as u64 > config.max_pixels
+    {
+        return Err(IngestError::TooManyPixels { width, height });
+    }
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(flatten_alpha(image))
+}
+
+/// heic / heif files are iso-bmff boxes with one of these brands
+fn is_heic(bytes: &[u8]) -> bool {
+    bytes.len() >= 12
+        && &bytes[4..8] == b"ftyp"
+        && matches!(
+            &bytes[8..12],
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1"
+        )
+}
+
+/// walks the jpeg markers up to the end of image marker, segments are skipped by their
+/// length so thumbnails in exif don't count and data appended after the image is allowed
+fn is_truncated_jpeg(bytes: &[u8]) -> bool {
+    // right after the start of image marker
+    let mut i = 2;
+    while i + 1 < bytes.len() {
+        if bytes[i] != 0xFF {
+            // entropy coded data
+            i += 1;
+            continue;
+        }
+        match bytes[i + 1] {
+            0xD9 => return false,
+            // fill byte
+            0xFF => i += 1,
+            // stuffed zero, restart markers and the other markers without a length
+            0x00 | 0x01 | 0xD0..=0xD8 => i += 2,
+            _ => {
+                if i + 3 >= bytes.len() {
+                    return true;
+                }
+                let length = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+                i += 2 + length;
+            }
+        }
+    }
+    true
+}
+
+/// blends transparent images onto white, dropping the alpha channel
+/// straight away would turn transparent pixels into whatever color they hold (often black)
+pub fn flatten_alpha(image: DynamicImage) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let flattened = RgbImage::from_fn(width, height, |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let alpha = a as f32 / 255.0;
+        let blend = |c: u8| (c as f32 * alpha + 255.0 * (1.0 - alpha)).round() as u8;
+        Rgb([blend(r), blend(g), blend(b)])
+    });
+    DynamicImage::ImageRgb8(flattened)
+}
+
+/// decodes a base64 image, with or without a `data:image/..;base64,` prefix
+pub fn decode_base64(data: &str, config: &IngestConfig) -> Result<DynamicImage, IngestError> {
+    let data = match data.split_once(";base64,") {
+        Some((prefix, data)) if prefix.starts_with("data:") => data,
+        _ => data,
+    };
+    let data = data.trim();
+    // refuse before allocating the decoded buffer
+    if data.len() / 4 * 3 > config.max_bytes {
+        return Err(IngestError::TooLarge {
+            limit: config.max_bytes,
+        });
+    }
+    let bytes = general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| IngestError::InvalidBase64(e.to_string()))?;
+    decode_image(&bytes, config)
+}
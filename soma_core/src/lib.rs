@@ -6,6 +6,7 @@
 
 pub mod onnx_backend;
 pub mod common_utils;
+pub mod image_ingest;
 
 #[cfg(feature="candle_models")]
 pub mod blip_model;
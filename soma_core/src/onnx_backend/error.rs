@@ -0,0 +1,83 @@
+//! errors returned while loading and validating onnx models
+
+use crate::onnx_backend::BackendKind;
+use std::fmt;
+
+/// why a model could not be loaded,
+/// `model` is a short name for the wrapper (`yolo`, `scrfd`, `arcface`, ..)
+#[derive(Debug)]
+pub enum ModelLoadError {
+    /// the weights file does not exist
+    NotFound { path: String },
+    /// the backend was not compiled in
+    BackendUnavailable { backend: BackendKind },
+    /// the backend failed to parse / optimize the model
+    Backend {
+        path: String,
+        backend: BackendKind,
+        source: anyhow::Error,
+    },
+    /// the model inputs do not match what the wrapper feeds it
+    InputMismatch {
+        model: String,
+        expected: String,
+        found: String,
+    },
+    /// the model outputs do not match what the wrapper decodes
+    OutputMismatch {
+        model: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for ModelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelLoadError::NotFound { path } => {
+                write!(f, "model weights not found at `{}`", path)
+            }
+            ModelLoadError::BackendUnavailable { backend } => write!(
+                f,
+                "inference backend `{}` is not compiled in, rebuild with the `{}` feature",
+                backend,
+                match backend {
+                    BackendKind::Ort => "onnxruntime",
+                    BackendKind::Tract => "smol",
+                }
+            ),
+            ModelLoadError::Backend {
+                path,
+                backend,
+                source,
+            } => write!(f, "`{}` failed to load `{}`: {:#}", backend, path, source),
+            ModelLoadError::InputMismatch {
+                model,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} model has unexpected inputs, expected {} but found {}",
+                model, expected, found
+            ),
+            ModelLoadError::OutputMismatch {
+                model,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} model has unexpected outputs, expected {} but found {}",
+                model, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModelLoadError::Backend { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! onnx inference backends.
+//!
+//! every model runs through an [InferenceBackend], `ort` (feature `onnxruntime`, default)
+//! or `tract` (feature `smol`). both can be compiled in at the same time and picked
+//! per model at runtime with [BackendKind].
+//!
+//! build with `--no-default-features --features smol`
+//! to drop the onnxruntime shared library entirely
+
+pub mod error;
+#[cfg(feature="onnxruntime")]
+pub mod ort_backend;
+#[cfg(feature="smol")]
+pub mod tract_backend;
+
+pub use error::ModelLoadError;
+
+use crate::common_utils::Bbox;
+use anyhow::{anyhow, bail, Error, Result};
+use image::DynamicImage;
+use ndarray::{ArrayD, ArrayViewD};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// FaceDetection is a `Vec<Bbox>` (for bbox detection of faces)
+///
+///
+/// FaceEmbedding is a Vec[f32; 512] face vector (latent)
+#[derive(Debug, Serialize, Deserialize)]
+pub enum InferenceResult {
+    FaceDetection(Vec<Bbox>),
+    FaceEmbedding(Vec<f32>),
+}
+
+/// which library runs the model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// onnxruntime through `ort`
+    Ort,
+    /// pure rust `tract`
+    Tract,
+}
+
+impl BackendKind {
+    /// `ort` when compiled in, `tract` otherwise
+    pub fn default_available() -> BackendKind {
+        if cfg!(feature = "onnxruntime") {
+            BackendKind::Ort
+        } else {
+            BackendKind::Tract
+        }
+    }
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        BackendKind::default_available()
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ort" | "onnxruntime" => Ok(BackendKind::Ort),
+            "tract" | "smol" => Ok(BackendKind::Tract),
+            _ => Err(anyhow!("unknown inference backend `{}`, expected `ort` or `tract`", s)),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Ort => write!(f, "ort"),
+            BackendKind::Tract => write!(f, "tract"),
+        }
+    }
+}
+
+/// how a model session is created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    /// intra-op threads per session, `None` lets the backend decide
+    /// (only used by `ort`, `tract` runs single threaded)
+    pub intra_threads: Option<usize>,
+}
+
+impl BackendConfig {
+    pub fn new(kind: BackendKind) -> BackendConfig {
+        BackendConfig {
+            kind,
+            intra_threads: None,
+        }
+    }
+
+    pub fn with_intra_threads(mut self, intra_threads: Option<usize>) -> BackendConfig {
+        self.intra_threads = intra_threads;
+        self
+    }
+}
+
+impl From<BackendKind> for BackendConfig {
+    fn from(kind: BackendKind) -> Self {
+        BackendConfig::new(kind)
+    }
+}
+
+/// name and shape of a model input / output,
+/// dynamic (or unknown) dimensions are `-1`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<i64>,
+}
+
+/// a loaded model that takes and returns f32 tensors
+pub trait InferenceBackend: Send + Sync + fmt::Debug {
+    fn kind(&self) -> BackendKind;
+
+    fn inputs(&self) -> &[TensorInfo];
+
+    fn outputs(&self) -> &[TensorInfo];
+
+    /// runs the model, `inputs` are matched by name,
+    /// outputs are returned in [InferenceBackend::outputs] order
+    fn run(&self, inputs: &[(&str, ArrayViewD<f32>)]) -> Result<Vec<ArrayD<f32>>, Error>;
+}
+
+impl TensorInfo {
+    /// `name[1, 3, 640, 640]`, used in load diagnostics
+    pub fn describe(&self) -> String {
+        let dims: Vec<String> = self
+            .shape
+            .iter()
+            .map(|x| if *x < 0 { "?".to_string() } else { x.to_string() })
+            .collect();
+        format!("`{}`[{}]", self.name, dims.join(", "))
+    }
+}
+
+fn describe_tensors(tensors: &[TensorInfo]) -> String {
+    if tensors.is_empty() {
+        return "nothing".to_string();
+    }
+    tensors
+        .iter()
+        .map(|x| x.describe())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// loads `model_path` on the requested backend,
+/// `input_shape` is only used by `tract` (which needs a concrete input shape to optimize)
+pub fn load_backend(
+    config: BackendConfig,
+    model_path: &str,
+    input_shape: &[usize],
+) -> Result<Box<dyn InferenceBackend>, ModelLoadError> {
+    if !std::path::Path::new(model_path).is_file() {
+        return Err(ModelLoadError::NotFound {
+            path: model_path.to_string(),
+        });
+    }
+    let kind = config.kind;
+    let loaded: Result<Box<dyn InferenceBackend>, Error> = match kind {
+        #[cfg(feature="onnxruntime")]
+        BackendKind::Ort => {
+            let _ = input_shape;
+            ort_backend::OrtBackend::load(model_path, config.intra_threads)
+                .map(|x| Box::new(x) as Box<dyn InferenceBackend>)
+        }
+        #[cfg(feature="smol")]
+        BackendKind::Tract => tract_backend::TractBackend::load(model_path, input_shape)
+            .map(|x| Box::new(x) as Box<dyn InferenceBackend>),
+        #[allow(unreachable_patterns)]
+        _ => return Err(ModelLoadError::BackendUnavailable { backend: kind }),
+    };
+    loaded.map_err(|source| ModelLoadError::Backend {
+        path: model_path.to_string(),
+        backend: kind,
+        source,
+    })
+}
+
+/// half precision not implemented !
+/// (probably never will be)
+///
+#[derive(Debug)]
+pub struct OnnxModel {
+    pub model: Box<dyn InferenceBackend>,
+    pub is_fp16: bool,
+}
+
+impl OnnxModel {
+    pub fn new(model: Box<dyn InferenceBackend>, is_fp16: bool) -> Result<OnnxModel, Error> {
+        Ok(OnnxModel { model, is_fp16 })
+    }
+
+    /// loads an onnx model on the default backend, see [BackendKind::default_available]
+    pub fn load(
+        model_path: &str,
+        input_shape: &[usize],
+        fp16: bool,
+    ) -> Result<OnnxModel, ModelLoadError> {
+        OnnxModel::load_with_backend(BackendConfig::default(), model_path, input_shape, fp16)
+    }
+
+    /// loads an onnx model on a specific backend
+    pub fn load_with_backend(
+        backend: BackendConfig,
+        model_path: &str,
+        input_shape: &[usize],
+        fp16: bool,
+    ) -> Result<OnnxModel, ModelLoadError> {
+        Ok(OnnxModel {
+            model: load_backend(backend, model_path, input_shape)?,
+            is_fp16: fp16,
+        })
+    }
+
+    pub fn backend(&self) -> BackendKind {
+        self.model.kind()
+    }
+
+    /// runs a single input model,
+    /// returns every output in model output order
+    pub fn run_f32(&self, input: ArrayViewD<f32>) -> Result<Vec<ArrayD<f32>>, Error> {
+        let input_name = match self.model.inputs().first() {
+            Some(info) => info.name.clone(),
+            None => bail!("model has no inputs"),
+        };
+        self.model.run(&[(input_name.as_str(), input)])
+    }
+
+    /// number of model outputs
+    pub fn output_count(&self) -> usize {
+        self.model.outputs().len()
+    }
+
+    /// shape of the first input, dynamic dimensions are `-1`
+    pub fn input_dims(&self) -> Vec<i64> {
+        self.model
+            .inputs()
+            .first()
+            .map(|x| x.shape.clone())
+            .unwrap_or_default()
+    }
+
+    /// checks the model has a single input compatible with `expected`,
+    /// `-1` in either shape matches any size
+    pub fn expect_input_shape(&self, model: &str, expected: &[i64]) -> Result<(), ModelLoadError> {
+        let inputs = self.model.inputs();
+        let matches = inputs.len() == 1
+            && inputs[0].shape.len() == expected.len()
+            && inputs[0]
+                .shape
+                .iter()
+                .zip(expected.iter())
+                .all(|(found, want)| *found < 0 || *want < 0 || found == want);
+        if matches {
+            return Ok(());
+        }
+        Err(ModelLoadError::InputMismatch {
+            model: model.to_string(),
+            expected: TensorInfo {
+                name: "input".to_string(),
+                shape: expected.to_vec(),
+            }
+            .describe(),
+            found: describe_tensors(inputs),
+        })
+    }
+
+    /// checks the number of model outputs is one of `expected`
+    pub fn expect_output_count(&self, model: &str, expected: &[usize]) -> Result<(), ModelLoadError> {
+        if expected.contains(&self.output_count()) {
+            return Ok(());
+        }
+        let counts: Vec<String> = expected.iter().map(|x| x.to_string()).collect();
+        Err(ModelLoadError::OutputMismatch {
+            model: model.to_string(),
+            expected: format!("{} output(s)", counts.join(" or ")),
+            found: describe_tensors(self.model.outputs()),
+        })
+    }
+
+    /// checks the last dimension of the first output, dynamic sizes are accepted
+    pub fn expect_output_width(&self, model: &str, expected: i64) -> Result<(), ModelLoadError> {
+        let last = self
+            .model
+            .outputs()
+            .first()
+            .and_then(|x| x.shape.last().copied());
+        match last {
+            Some(x) if x < 0 || x == expected => Ok(()),
+            _ => Err(ModelLoadError::OutputMismatch {
+                model: model.to_string(),
+                expected: format!("a first output of width {}", expected),
+                found: describe_tensors(self.model.outputs()),
+            }),
+        }
+    }
+}
+
+pub trait Inference {
+    /// runs a forward pass,
+    /// outputs [InferenceResult]
+    ///
+    fn forward(
+        &self,
+        input_image: &DynamicImage,
+        confidence_threshold: f32,
+    ) -> Result<InferenceResult, Error>;
+
+    /// loads the model with its default input size
+    fn load(model_path: &str, fp16: bool) -> Result<OnnxModel, ModelLoadError>;
+}
@@ -0,0 +1,88 @@
+//! `ort` (onnxruntime) backend
+
+use crate::onnx_backend::{BackendKind, InferenceBackend, TensorInfo};
+use anyhow::{Error, Result};
+use ndarray::{ArrayD, ArrayViewD};
+use ort::{CPUExecutionProvider, GraphOptimizationLevel, Session};
+
+#[derive(Debug)]
+pub struct OrtBackend {
+    pub session: Session,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+}
+
+impl OrtBackend {
+    pub fn load(model_path: &str, intra_threads: Option<usize>) -> Result<OrtBackend, Error> {
+        let mut builder = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_execution_providers([CPUExecutionProvider::default().build()])?;
+        if let Some(threads) = intra_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        let session = builder.commit_from_file(model_path)?;
+        Ok(OrtBackend::from_session(session))
+    }
+
+    pub fn from_session(session: Session) -> OrtBackend {
+        let inputs = session
+            .inputs
+            .iter()
+            .map(|input| TensorInfo {
+                name: input.name.clone(),
+                shape: input
+                    .input_type
+                    .tensor_dimensions()
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let outputs = session
+            .outputs
+            .iter()
+            .map(|output| TensorInfo {
+                name: output.name.clone(),
+                shape: output
+                    .output_type
+                    .tensor_dimensions()
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect();
+        OrtBackend {
+            session,
+            inputs,
+            outputs,
+        }
+    }
+}
+
+impl InferenceBackend for OrtBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Ort
+    }
+
+    fn inputs(&self) -> &[TensorInfo] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[TensorInfo] {
+        &self.outputs
+    }
+
+    fn run(&self, inputs: &[(&str, ArrayViewD<f32>)]) -> Result<Vec<ArrayD<f32>>, Error> {
+        let mut session_inputs = vec![];
+        for (name, input) in inputs {
+            session_inputs.extend(ort::inputs![*name => input.view()]?);
+        }
+        let outputs = self.session.run(session_inputs)?;
+        self.outputs
+            .iter()
+            .map(|output| {
+                Ok(outputs[output.name.as_str()]
+                    .try_extract_tensor::<f32>()?
+                    .into_owned())
+            })
+            .collect()
+    }
+}
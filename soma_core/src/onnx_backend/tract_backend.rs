@@ -0,0 +1,236 @@
+//! pure rust `tract` backend (feature `smol`)
+
+use crate::onnx_backend::{BackendKind, InferenceBackend, TensorInfo};
+use anyhow::{anyhow, Error, Result};
+use ndarray::{ArrayD, ArrayViewD};
+use tract_onnx::prelude::*;
+
+pub type TractPlan = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+#[derive(Debug)]
+pub struct TractBackend {
+    pub plan: TractPlan,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+}
+
+fn outlet_info(model: &TypedModel, outlet: OutletId) -> TensorInfo {
+    let name = model
+        .outlet_label(outlet)
+        .map(|x| x.to_string())
+        .unwrap_or_else(|| model.node(outlet.node).name.clone());
+    let shape = model
+        .outlet_fact(outlet)
+        .ok()
+        .and_then(|fact| fact.shape.as_concrete().map(|x| x.to_vec()))
+        .map(|dims| dims.into_iter().map(|x| x as i64).collect())
+        .unwrap_or_default();
+    TensorInfo { name, shape }
+}
+
+impl TractBackend {
+    /// tract optimizes for a concrete input shape, so it has to be known at load time
+    pub fn load(model_path: &str, input_shape: &[usize]) -> Result<TractBackend, Error> {
+        let plan = tract_onnx::onnx()
+            .model_for_path(model_path)?
+            .with_input_fact(0, f32::fact(input_shape).into())?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(TractBackend::from_plan(plan))
+    }
+
+    pub fn from_plan(plan: TractPlan) -> TractBackend {
+        let model = plan.model();
+        let inputs = model
+            .input_outlets()
+            .map(|outlets| outlets.iter().map(|o| outlet_info(model, *o)).collect())
+            .unwrap_or_default();
+        let outputs = model
+            .output_outlets()
+            .map(|outlets| outlets.iter().map(|o| outlet_info(model, *o)).collect())
+            .unwrap_or_default();
+        TractBackend {
+            plan,
+            inputs,
+            outputs,
+        }
+    }
+}
+
+impl InferenceBackend for TractBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Tract
+    }
+
+    fn inputs(&self) -> &[TensorInfo] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[TensorInfo] {
+        &self.outputs
+    }
+
+    fn run(&self, inputs: &[(&str, ArrayViewD<f32>)]) -> Result<Vec<ArrayD<f32>>, Error> {
+        // tract takes inputs positionally, reorder them by name
+        let mut ordered: TVec<TValue> = tvec!();
+        for info in self.inputs.iter() {
+            let (_, input) = inputs
+                .iter()
+                .find(|(name, _)| *name == info.name)
+                .ok_or_else(|| anyhow!("missing model input `{}`", info.name))?;
+            let tensor: Tensor = input.to_owned().into();
+            ordered.push(tensor.into());
+        }
+        let outputs = self.plan.run(ordered)?;
+        outputs
+            .iter()
+            .map(|output| Ok(output.to_array_view::<f32>()?.to_owned()))
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "onnxruntime"))]
+mod tests {
+    //! `ort` / `tract` parity on the face models, same input through both backends.
+    //! the models are not in the repo, `SOMA_YOLO_MODEL`, `SOMA_SCRFD_MODEL` and
+    //! `SOMA_ARCFACE_MODEL` point at them (default `../soma_face/models/`).
+    //! run with `cargo test --features smol -- --ignored`
+
+    use super::*;
+    use crate::onnx_backend::ort_backend::OrtBackend;
+    use crate::onnx_backend::OnnxModel;
+    use ndarray::{Axis, IxDyn, Slice};
+
+    const YOLO: (&str, &str) = ("SOMA_YOLO_MODEL", "../soma_face/models/yoloface_8n.onnx");
+    const SCRFD: (&str, &str) = ("SOMA_SCRFD_MODEL", "../soma_face/models/det_10g.onnx");
+    const ARCFACE: (&str, &str) = (
+        "SOMA_ARCFACE_MODEL",
+        "../soma_face/models/arcfaceresnet100-8.onnx",
+    );
+
+    /// outputs match if `|ort - tract| <= ATOL + RTOL * |ort|` everywhere
+    const ATOL: f32 = 1e-3;
+    const RTOL: f32 = 1e-3;
+
+    fn model_path((env, default): (&str, &str)) -> String {
+        let path = std::env::var(env).unwrap_or_else(|_| default.to_string());
+        assert!(
+            std::path::Path::new(&path).is_file(),
+            "no model at `{}`, set `{}`",
+            path,
+            env
+        );
+        path
+    }
+
+    /// deterministic NCHW input in [-1, 1], every image of the batch is different
+    fn input(shape: &[usize]) -> ArrayD<f32> {
+        ArrayD::from_shape_fn(IxDyn(shape), |idx| {
+            (idx[3] as f32 * 0.05 + idx[2] as f32 * 0.03 + idx[1] as f32 + idx[0] as f32 * 0.7)
+                .sin()
+        })
+    }
+
+    fn run(backend: &dyn InferenceBackend, input: ArrayViewD<f32>) -> Vec<ArrayD<f32>> {
+        let name = backend.inputs()[0].name.clone();
+        backend.run(&[(name.as_str(), input)]).unwrap()
+    }
+
+    fn assert_close(what: &str, ort: ArrayViewD<f32>, tract: ArrayViewD<f32>) {
+        assert_eq!(ort.shape(), tract.shape(), "{} shapes differ", what);
+        let (worst, at) = ort
+            .iter()
+            .zip(tract.iter())
+            .map(|(a, b)| (a - b).abs() - RTOL * a.abs())
+            .enumerate()
+            .fold((f32::MIN, 0), |(worst, at), (n, x)| match x > worst {
+                true => (x, n),
+                false => (worst, at),
+            });
+        assert!(
+            worst <= ATOL,
+            "{} differs by {} over the tolerance at flat index {}",
+            what,
+            worst - ATOL,
+            at
+        );
+    }
+
+    /// every output of a single image run, in model output order
+    fn check_parity(model: (&str, &str), input_shape: &[usize]) {
+        let path = model_path(model);
+        let ort = OrtBackend::load(&path, None).unwrap();
+        let tract = TractBackend::load(&path, input_shape).unwrap();
+        let x = input(input_shape);
+        let ort_outputs = run(&ort, x.view());
+        let tract_outputs = run(&tract, x.view());
+        assert_eq!(ort_outputs.len(), tract_outputs.len());
+        for (n, (a, b)) in ort_outputs.iter().zip(tract_outputs.iter()).enumerate() {
+            assert_close(&format!("{} output {}", path, n), a.view(), b.view());
+        }
+    }
+
+    /// `tract` is optimized for a batch of 1, so callers fall back to one run per image.
+    /// those runs must match the batched `ort` run image by image
+    fn check_per_image_fallback(model: (&str, &str), image_shape: &[usize]) {
+        let path = model_path(model);
+        let ort = OrtBackend::load(&path, None).unwrap();
+        assert!(
+            ort.inputs()[0].shape.first().is_some_and(|x| *x < 0),
+            "`{}` has a fixed batch size",
+            path
+        );
+        let single = [&[1][..], image_shape].concat();
+        let tract = TractBackend::load(&path, &single).unwrap();
+        let tract = OnnxModel::new(Box::new(tract), false).unwrap();
+        // what `supports_batching` looks at
+        assert_eq!(tract.input_dims().first(), Some(&1));
+        let batch_size = 3;
+        let batch = input(&[&[batch_size][..], image_shape].concat());
+        let ort_outputs = run(&ort, batch.view());
+        for n in 0..batch_size {
+            let image = batch.slice_axis(Axis(0), Slice::from(n..n + 1));
+            let tract_outputs = tract.run_f32(image).unwrap();
+            for (k, (a, b)) in ort_outputs.iter().zip(tract_outputs.iter()).enumerate() {
+                let a = a.slice_axis(Axis(0), Slice::from(n..n + 1));
+                assert_close(&format!("{} image {} output {}", path, n, k), a, b.view());
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn yolo_parity() {
+        check_parity(YOLO, &[1, 3, 640, 640]);
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn scrfd_parity() {
+        check_parity(SCRFD, &[1, 3, 640, 640]);
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn scrfd_parity_non_square() {
+        check_parity(SCRFD, &[1, 3, 384, 640]);
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn arcface_parity() {
+        check_parity(ARCFACE, &[1, 3, 112, 112]);
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn yolo_per_image_fallback() {
+        check_per_image_fallback(YOLO, &[3, 640, 640]);
+    }
+
+    #[test]
+    #[ignore = "needs models, set SOMA_*"]
+    fn arcface_per_image_fallback() {
+        check_per_image_fallback(ARCFACE, &[3, 112, 112]);
+    }
+}
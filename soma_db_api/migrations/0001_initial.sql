@@ -0,0 +1,20 @@
+-- tables as init_pool used to create them, IF NOT EXISTS so databases
+-- created before the migrations table existed pick up from here
+CREATE TABLE IF NOT EXISTS frame_tags (
+    id bigserial PRIMARY KEY,
+    frame_name varchar(512),
+    original_source varchar(512),
+    orignial_type int,
+    face_count int,
+    frame_tags varchar(4096)
+);
+
+CREATE EXTENSION IF NOT EXISTS vector;
+
+CREATE TABLE IF NOT EXISTS face_embeddings (
+    id bigserial PRIMARY KEY,
+    name varchar(255),
+    face_uuid varchar(512) NOT NULL,
+    gender int,
+    embedding vector(512)
+);
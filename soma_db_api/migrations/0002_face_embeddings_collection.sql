@@ -0,0 +1 @@
+ALTER TABLE face_embeddings ADD COLUMN IF NOT EXISTS collection varchar(255);
@@ -0,0 +1,13 @@
+-- fixes the `orignial_type` typo
+DO $$
+BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema()
+          AND table_name = 'frame_tags'
+          AND column_name = 'orignial_type'
+    ) THEN
+        ALTER TABLE frame_tags RENAME COLUMN orignial_type TO original_type;
+    END IF;
+END
+$$;
@@ -0,0 +1,47 @@
+-- a person groups any number of face_embeddings rows, `person_uuid` is the stable id.
+CREATE TABLE IF NOT EXISTS persons (
+    id bigserial PRIMARY KEY,
+    person_uuid varchar(512) NOT NULL UNIQUE,
+    name varchar(255),
+    gender int,
+    collection varchar(255),
+    metadata jsonb NOT NULL DEFAULT '{}'::jsonb,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+ALTER TABLE face_embeddings
+    ADD COLUMN IF NOT EXISTS person_id bigint REFERENCES persons (id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS face_embeddings_person_id_idx ON face_embeddings (person_id);
+
+-- backfill. every enrollment has its own face_uuid, so faces are grouped by
+-- name + collection instead: one person per named face in a collection.
+-- `placeholder` is what the gateway used to send for every face, it names nobody
+INSERT INTO persons (person_uuid, name, gender, collection)
+SELECT DISTINCT ON (name, collection) gen_random_uuid()::text, name, gender, collection
+FROM face_embeddings
+WHERE person_id IS NULL AND name IS NOT NULL AND name NOT IN ('', 'placeholder')
+ORDER BY name, collection, id;
+
+UPDATE face_embeddings f
+SET person_id = p.id
+FROM persons p
+WHERE f.person_id IS NULL
+  AND f.name NOT IN ('', 'placeholder')
+  AND p.name = f.name
+  AND p.collection IS NOT DISTINCT FROM f.collection;
+
+-- unnamed faces cannot be told apart, each face_uuid becomes its own person
+-- (keyed by the face_uuid). enrollments of the same person have to be merged
+-- by hand with `POST /persons/merge`, until then `/identify` sees them as
+-- different identities
+INSERT INTO persons (person_uuid, name, gender, collection)
+SELECT DISTINCT ON (face_uuid) face_uuid, name, gender, collection
+FROM face_embeddings
+WHERE person_id IS NULL
+ORDER BY face_uuid, id
+ON CONFLICT (person_uuid) DO NOTHING;
+
+UPDATE face_embeddings f
+SET person_id = p.id
+FROM persons p
+WHERE f.person_id IS NULL AND p.person_uuid = f.face_uuid;
@@ -18,6 +18,10 @@ pub struct GetFaceDetailResponse {
     pub face_uuid: String,
     pub gender: Option<i32>,
     pub embedding: Vec<f32>,
+    #[serde(default)]
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub person_uuid: Option<String>,
 }
 
 /// embedding: face vector [f32; 512]
@@ -25,12 +29,21 @@ pub struct GetFaceDetailResponse {
 /// name: name of the face , optional
 ///
 /// gender: 0 = male , 1 = female. etc.  
+///
+/// collection: tenant / collection the face belongs to, optional
+///
+/// person_uuid: adds the face to this existing person, when unset a new person is
+/// created from name / gender / collection
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InsertFaceRequest {
     pub embedding: Vec<f32>,
     pub name: Option<String>,
     pub gender: Option<i64>,
     pub face_uuid: String,
+    #[serde(default)]
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub person_uuid: Option<String>,
 }
 
 impl InsertFaceRequest {
@@ -45,10 +58,20 @@ impl InsertFaceRequest {
             name,
             gender,
             face_uuid,
+            collection: None,
+            person_uuid: None,
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InsertFaceResponse {
+    pub status: i32,
+    pub message: String,
+    /// person the face was added to
+    pub person_uuid: String,
+}
+
 #[derive(Debug, MultipartForm)]
 pub struct GetSimilarFacesByImageRequest {
     face: TempFile,
@@ -69,10 +92,59 @@ pub struct GetSimilarFaceByImageRequest {
 }
 
 
+/// optional filters of the similarity searches, every set field must match.
+///
+/// min_similarity: drop faces under this cosine similarity (-1 - 1)
+///
+/// exclude_face_uuid: leave this face out, e.g. the face the query vector came from
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaceSearchFilter {
+    pub min_similarity: Option<f64>,
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub collection: Option<String>,
+    pub exclude_face_uuid: Option<String>,
+}
+
+/// per request vector index knobs, unset keeps the postgres settings.
+///
+/// ef_search: hnsw candidate list size (pgvector default 40), must be at least `count`
+/// or fewer rows come back. higher is slower with better recall
+///
+/// probes: ivfflat lists scanned (pgvector default 1), higher is slower with better recall
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SearchTuning {
+    pub ef_search: Option<u32>,
+    pub probes: Option<u32>,
+}
+
+impl SearchTuning {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ef_search) = self.ef_search {
+            if !(1..=1000).contains(&ef_search) {
+                return Err(format!("ef_search must be between 1 and 1000, got {}", ef_search));
+            }
+        }
+        if let Some(probes) = self.probes {
+            if !(1..=32768).contains(&probes) {
+                return Err(format!("probes must be between 1 and 32768, got {}", probes));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// exclude_self: leave `face_uuid` itself out of the results, defaults to false
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetSimilarFacesByUuidRequest {
     pub face_uuid: String,
     pub count: i64,
+    #[serde(default)]
+    pub exclude_self: bool,
+    #[serde(flatten)]
+    pub filter: FaceSearchFilter,
+    #[serde(flatten)]
+    pub tuning: SearchTuning,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -84,5 +156,60 @@ pub struct GetSimilarFacesByUuidResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetSimilarFacesByEmbeddingRequest {
     pub face_embedding: Vec<f32>,
-    pub count: i64
+    pub count: i64,
+    #[serde(flatten)]
+    pub filter: FaceSearchFilter,
+    #[serde(flatten)]
+    pub tuning: SearchTuning,
+}
+
+/// face_embedding: face vector [f32; 512]
+///
+/// threshold / margin: override the server defaults (`IDENTIFY_THRESHOLD` / `IDENTIFY_MARGIN`)
+///
+/// ef_search / probes: see [SearchTuning]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentifyRequest {
+    pub face_embedding: Vec<f32>,
+    pub threshold: Option<f64>,
+    pub margin: Option<f64>,
+    #[serde(flatten)]
+    pub tuning: SearchTuning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentifyStatus {
+    Identified,
+    Unknown,
+}
+
+/// `face` is only set when `status` is `identified`,
+/// the similarities are reported either way so callers can see how close it was.
+/// `second_best_similarity` is the best face of another person
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentifyResponse {
+    pub status: IdentifyStatus,
+    pub face: Option<GetFaceDetailResponse>,
+    pub cosine_similarity: Option<f64>,
+    pub second_best_similarity: Option<f64>,
+    pub threshold: f64,
+    pub margin: f64,
+    pub message: String,
+}
+
+/// body of `POST /admin/rebuild_index`, unset fields keep the server config
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RebuildIndexRequest {
+    pub kind: Option<String>,
+    pub m: Option<u32>,
+    pub ef_construction: Option<u32>,
+    pub lists: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VectorIndexResponse {
+    /// `indexdef` of the indexes on `embedding`, empty means exact scans
+    pub indexes: Vec<String>,
+    pub message: String,
 }
@@ -1,5 +1,6 @@
 pub mod face;
 pub mod frame;
+pub mod person;
 
 use serde::{Deserialize, Serialize};
 
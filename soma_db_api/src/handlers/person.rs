@@ -0,0 +1,96 @@
+use crate::handlers::face::SearchTuning;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// name / gender / collection: same meaning as on [crate::handlers::face::InsertFaceRequest]
+///
+/// metadata: any json object, defaults to `{}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePersonRequest {
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub collection: Option<String>,
+    pub metadata: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetPersonRequest {
+    pub person_uuid: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonResponse {
+    pub person_uuid: String,
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub collection: Option<String>,
+    pub metadata: Value,
+    /// embeddings linked to the person
+    pub face_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonDetailResponse {
+    pub person: PersonResponse,
+    pub face_uuids: Vec<String>,
+}
+
+/// moves every embedding of `merge_person_uuids` to `person_uuid` and deletes those persons,
+/// `person_uuid` keeps its own name and metadata
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergePersonsRequest {
+    pub person_uuid: String,
+    pub merge_person_uuids: Vec<String>,
+}
+
+/// moves the embeddings of `face_uuids` from `person_uuid` to a new person,
+/// unset fields are copied from `person_uuid`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SplitPersonRequest {
+    pub person_uuid: String,
+    pub face_uuids: Vec<String>,
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub collection: Option<String>,
+    pub metadata: Option<Value>,
+}
+
+/// how the similarities of a person's embeddings are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PersonAggregation {
+    /// best embedding, forgiving with a few bad enrollment photos
+    #[default]
+    Max,
+    /// average over every embedding, rewards persons that match consistently
+    Mean,
+}
+
+/// face_embedding: face vector [f32; 512]
+///
+/// count: number of persons returned
+///
+/// min_similarity: drop persons whose aggregated similarity is under it (-1 - 1)
+///
+/// collection: only faces of this collection are searched and scored
+///
+/// ef_search / probes: see [SearchTuning], candidates are fetched per face
+/// so `ef_search` should be well above `count`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchPersonsRequest {
+    pub face_embedding: Vec<f32>,
+    pub count: i64,
+    #[serde(default)]
+    pub aggregation: PersonAggregation,
+    pub min_similarity: Option<f64>,
+    pub collection: Option<String>,
+    #[serde(flatten)]
+    pub tuning: SearchTuning,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonMatchResponse {
+    pub person: PersonResponse,
+    /// aggregated over all the person's embeddings with the requested [PersonAggregation]
+    pub cosine_similarity: f64,
+}
@@ -0,0 +1,66 @@
+use crate::handlers::face::{RebuildIndexRequest, VectorIndexResponse};
+use crate::handlers::GenericResponse;
+use crate::utils::vector_index::{
+    rebuild_vector_index, vector_index_definitions, IndexKind, VectorIndexConfig,
+};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{get, post, web};
+use actix_web::HttpResponse;
+use deadpool_postgres::Pool;
+
+/// the vector indexes currently on `face_embeddings`
+#[get("/index")]
+pub async fn get_vector_index(pool: web::Data<Pool>) -> actix_web::Result<HttpResponse> {
+    let client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let indexes = vector_index_definitions(&client)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(VectorIndexResponse {
+        indexes,
+        message: String::from("success"),
+    }))
+}
+
+/// rebuilds the vector index, the body overrides the server config field by field.
+/// blocks until the build is done, which can take a while on a big table
+#[post("/rebuild_index")]
+pub async fn rebuild_index(
+    pool: web::Data<Pool>,
+    config: web::Data<VectorIndexConfig>,
+    form: Option<web::Json<RebuildIndexRequest>>,
+) -> actix_web::Result<HttpResponse> {
+    let form = form.map(|x| x.into_inner()).unwrap_or_default();
+    let kind = match form.kind.as_deref().map(IndexKind::parse) {
+        None => config.kind,
+        Some(Some(kind)) => kind,
+        Some(None) => {
+            return Ok(HttpResponse::BadRequest().json(GenericResponse {
+                status: 400,
+                message: String::from("invalid kind, use hnsw, ivfflat or none"),
+            }))
+        }
+    };
+    let index_config = VectorIndexConfig {
+        kind,
+        m: form.m.unwrap_or(config.m),
+        ef_construction: form.ef_construction.unwrap_or(config.ef_construction),
+        lists: form.lists.unwrap_or(config.lists),
+    };
+    if let Err(message) = index_config.validate() {
+        return Ok(HttpResponse::BadRequest().json(GenericResponse {
+            status: 400,
+            message,
+        }));
+    }
+    let client = pool.get().await.map_err(ErrorInternalServerError)?;
+    rebuild_vector_index(&client, &index_config)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let indexes = vector_index_definitions(&client)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(VectorIndexResponse {
+        indexes,
+        message: String::from("success"),
+    }))
+}
@@ -0,0 +1,214 @@
+use crate::handlers::face::{
+    GetFaceDetailResponse, IdentifyRequest, IdentifyResponse, IdentifyStatus,
+};
+use crate::handlers::GenericResponse;
+use crate::operators::queries::{face_from_row, FACE_COLUMNS};
+use crate::utils::vector_index::apply_search_tuning;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{post, web};
+use actix_web::{HttpRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use std::env;
+
+/// nearest rows fetched per query, grouped by person afterwards (see [identity_key]).
+/// leaves room for the best person's other enrollments ahead of the second best identity
+const IDENTIFY_CANDIDATES: i64 = 32;
+
+/// open-set thresholds of `/identify`, read from `IDENTIFY_THRESHOLD` and `IDENTIFY_MARGIN`
+#[derive(Debug, Clone, Copy)]
+pub struct IdentifyConfig {
+    /// minimum cosine similarity of the best identity
+    pub threshold: f64,
+    /// minimum gap between the best and the second best identity,
+    /// two people that are about as close are reported as unknown
+    pub margin: f64,
+}
+
+impl IdentifyConfig {
+    pub fn from_env() -> IdentifyConfig {
+        let read = |key: &str, default: f64| {
+            env::var(key)
+                .ok()
+                .map(|x| x.parse::<f64>().unwrap_or_else(|_| panic!("invalid {} `{}`", key, x)))
+                .unwrap_or(default)
+        };
+        IdentifyConfig {
+            threshold: read("IDENTIFY_THRESHOLD", 0.35),
+            margin: read("IDENTIFY_MARGIN", 0.05),
+        }
+    }
+}
+
+/// 1:N identification, returns the closest enrolled identity only if it clears
+/// the threshold and is clearly ahead of the next identity, `unknown` otherwise
+#[post("/identify")]
+pub async fn identify_face(
+    pool: web::Data<Pool>,
+    config: web::Data<IdentifyConfig>,
+    form: web::Json<IdentifyRequest>,
+    _: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    if form.face_embedding.len() != 512 {
+        return Ok(HttpResponse::BadRequest().json(GenericResponse {
+            status: 400,
+            message: String::from(
+                "invalid vector dimension , input vector must be exactly 512 long!",
+            ),
+        }));
+    }
+    if let Err(message) = form.tuning.validate() {
+        return Ok(HttpResponse::BadRequest().json(GenericResponse {
+            status: 400,
+            message,
+        }));
+    }
+    let threshold = form.threshold.unwrap_or(config.threshold);
+    let margin = form.margin.unwrap_or(config.margin);
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let face_embedding = pgvector::Vector::from(form.face_embedding.to_owned());
+    let transaction = client.transaction().await.map_err(ErrorInternalServerError)?;
+    apply_search_tuning(&transaction, &form.tuning)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let sql = format!(
+        "SELECT {}, 1 - (embedding <=> $1) AS cosine_similarity FROM face_embeddings ORDER BY embedding <=> $1 LIMIT $2",
+        FACE_COLUMNS
+    );
+    let rows = transaction
+        .query(&sql, &[&face_embedding, &IDENTIFY_CANDIDATES])
+        .await
+        .map_err(ErrorInternalServerError)?;
+    transaction.commit().await.map_err(ErrorInternalServerError)?;
+    let candidates = rows
+        .iter()
+        .map(|row| (face_from_row(row), row.get("cosine_similarity")))
+        .collect();
+    Ok(HttpResponse::Ok().json(pick_identity(candidates, threshold, margin)))
+}
+
+/// what candidates are grouped by. every enrollment gets its own `face_uuid`,
+/// the person is what ties several enrollments of the same face together.
+/// a face without a person (its person was deleted by hand) stands on its own
+fn identity_key(face: &GetFaceDetailResponse) -> &str {
+    face.person_uuid.as_deref().unwrap_or(&face.face_uuid)
+}
+
+/// applies the threshold and margin to `candidates`, sorted most similar first.
+/// the margin is measured against the best face of another identity, never
+/// against another enrollment of the same person
+fn pick_identity(
+    candidates: Vec<(GetFaceDetailResponse, f64)>,
+    threshold: f64,
+    margin: f64,
+) -> IdentifyResponse {
+    // the first candidate of each identity is its best match
+    let mut identities: Vec<(GetFaceDetailResponse, f64)> = vec![];
+    for (face, similarity) in candidates {
+        if identities
+            .iter()
+            .any(|(seen, _)| identity_key(seen) == identity_key(&face))
+        {
+            continue;
+        }
+        identities.push((face, similarity));
+        if identities.len() == 2 {
+            break;
+        }
+    }
+    let mut identities = identities.into_iter();
+    let best = identities.next();
+    let second_best_similarity = identities.next().map(|(_, similarity)| similarity);
+    let (status, face, cosine_similarity, message) = match best {
+        None => (
+            IdentifyStatus::Unknown,
+            None,
+            None,
+            String::from("no faces are enrolled"),
+        ),
+        Some((_, similarity)) if similarity < threshold => (
+            IdentifyStatus::Unknown,
+            None,
+            Some(similarity),
+            String::from("the closest face is below the similarity threshold"),
+        ),
+        Some((_, similarity))
+            if second_best_similarity.is_some_and(|second| similarity - second < margin) =>
+        {
+            (
+                IdentifyStatus::Unknown,
+                None,
+                Some(similarity),
+                String::from("the two closest identities are too close to tell apart"),
+            )
+        }
+        Some((face, similarity)) => (
+            IdentifyStatus::Identified,
+            Some(face),
+            Some(similarity),
+            String::from("success"),
+        ),
+    };
+    IdentifyResponse {
+        status,
+        face,
+        cosine_similarity,
+        second_best_similarity,
+        threshold,
+        margin,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(face_uuid: &str, person_uuid: &str) -> GetFaceDetailResponse {
+        GetFaceDetailResponse {
+            id: 0,
+            name: None,
+            face_uuid: face_uuid.to_string(),
+            gender: None,
+            embedding: vec![],
+            collection: None,
+            person_uuid: Some(person_uuid.to_string()),
+        }
+    }
+
+    #[test]
+    fn enrollments_of_one_person_are_one_identity() {
+        // alice is enrolled twice, both enrollments are closer than bob
+        let candidates = vec![
+            (face("alice-1", "alice"), 0.80),
+            (face("alice-2", "alice"), 0.79),
+            (face("bob-1", "bob"), 0.50),
+        ];
+        let response = pick_identity(candidates, 0.35, 0.05);
+        assert_eq!(response.status, IdentifyStatus::Identified);
+        assert_eq!(response.face.unwrap().face_uuid, "alice-1");
+        assert_eq!(response.cosine_similarity, Some(0.80));
+        assert_eq!(response.second_best_similarity, Some(0.50));
+    }
+
+    #[test]
+    fn margin_applies_across_identities() {
+        let candidates = vec![
+            (face("alice-1", "alice"), 0.80),
+            (face("alice-2", "alice"), 0.79),
+            (face("bob-1", "bob"), 0.77),
+        ];
+        let response = pick_identity(candidates, 0.35, 0.05);
+        assert_eq!(response.status, IdentifyStatus::Unknown);
+        assert!(response.face.is_none());
+        assert_eq!(response.second_best_similarity, Some(0.77));
+    }
+
+    #[test]
+    fn below_threshold_is_unknown() {
+        let candidates = vec![(face("alice-1", "alice"), 0.30)];
+        let response = pick_identity(candidates, 0.35, 0.05);
+        assert_eq!(response.status, IdentifyStatus::Unknown);
+        assert_eq!(response.cosine_similarity, Some(0.30));
+        assert_eq!(response.second_best_similarity, None);
+    }
+}
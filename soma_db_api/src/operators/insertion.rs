@@ -1,5 +1,6 @@
-use crate::handlers::face::InsertFaceRequest;
+use crate::handlers::face::{InsertFaceRequest, InsertFaceResponse};
 use crate::handlers::GenericResponse;
+use actix_web::error::ErrorInternalServerError;
 use actix_web::{post, web};
 use actix_web::{HttpRequest, HttpResponse};
 use deadpool_postgres::Pool;
@@ -21,20 +22,54 @@ pub async fn insert_face_vector(
             ),
         }))
     } else {
-        let client = pool.get().await.unwrap();
+        let mut client = pool.get().await.unwrap();
         // let gender_string: Option<String> = match form.gender {
         //     Some(gender) => Some(gender.to_string()),
         //     None => None
         // };
+        // the column is an `int`, an i64 does not bind to it
+        let gender = form.gender.map(|x| x as i32);
+        let transaction = client.transaction().await.map_err(ErrorInternalServerError)?;
+        let person = match &form.person_uuid {
+            Some(person_uuid) => transaction
+                .query_opt(
+                    "SELECT id, person_uuid FROM persons WHERE person_uuid = $1",
+                    &[person_uuid],
+                )
+                .await
+                .map_err(ErrorInternalServerError)?,
+            None => Some(
+                transaction
+                    .query_one(
+                        "INSERT INTO persons (person_uuid, name, gender, collection) VALUES (gen_random_uuid()::text, $1, $2, $3) RETURNING id, person_uuid",
+                        &[&form.name, &gender, &form.collection],
+                    )
+                    .await
+                    .map_err(ErrorInternalServerError)?,
+            ),
+        };
+        let Some(person) = person else {
+            return Ok(HttpResponse::NotFound().json(GenericResponse {
+                status: 404,
+                message: String::from("no person with the given person_uuid"),
+            }));
+        };
+        let person_id: i64 = person.get("id");
         let pgvec_vector = Vector::from(form.embedding.to_owned());
-        let statement = client.prepare("INSERT INTO face_embeddings (name, embedding, gender, face_uuid) VALUES ($1, $2, $3, $4)").await.unwrap();
-        client
+        let statement = transaction.prepare("INSERT INTO face_embeddings (name, embedding, gender, face_uuid, collection, person_id) VALUES ($1, $2, $3, $4, $5, $6)").await.unwrap();
+        transaction
             .execute(
                 &statement,
-                &[&form.name, &pgvec_vector, &form.gender, &form.face_uuid],
+                &[&form.name, &pgvec_vector, &gender, &form.face_uuid, &form.collection, &person_id],
             )
             .await
             .unwrap();
-        Ok(HttpResponse::Created().json(GenericResponse::ok()))
+        transaction.commit().await.map_err(ErrorInternalServerError)?;
+        let ok = GenericResponse::ok();
+        Ok(HttpResponse::Created().json(InsertFaceResponse {
+            status: ok.status,
+            message: ok.message,
+            person_uuid: person.get("person_uuid"),
+        }))
     }
 }
@@ -1,4 +1,7 @@
+pub mod admin;
+pub mod identify;
 pub mod insertion;
+pub mod persons;
 pub mod queries;
 
 use actix_web::{http::header::ContentType, HttpResponse};
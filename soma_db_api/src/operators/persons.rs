@@ -0,0 +1,335 @@
+use crate::handlers::person::{
+    CreatePersonRequest, GetPersonRequest, MergePersonsRequest, PersonAggregation,
+    PersonDetailResponse, PersonMatchResponse, PersonResponse, SearchPersonsRequest,
+    SplitPersonRequest,
+};
+use crate::handlers::GenericResponse;
+use crate::utils::vector_index::apply_search_tuning;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{post, web};
+use actix_web::HttpResponse;
+use deadpool_postgres::tokio_postgres::{Error, Row};
+use deadpool_postgres::{GenericClient, Pool};
+use serde_json::{json, Value};
+
+/// nearest faces fetched per requested person in `/search_persons`,
+/// their persons are then scored over all of their embeddings
+const PERSON_CANDIDATES_PER_RESULT: i64 = 8;
+
+const MAX_PERSON_CANDIDATES: i64 = 1000;
+
+/// `persons p` columns read by [person_from_row], `face_count` has to be selected too
+const PERSON_COLUMNS: &str = "p.person_uuid, p.name, p.gender, p.collection, p.metadata";
+
+fn person_from_row(row: &Row) -> PersonResponse {
+    PersonResponse {
+        person_uuid: row.get("person_uuid"),
+        name: row.get("name"),
+        gender: row.get("gender"),
+        collection: row.get("collection"),
+        metadata: row.get("metadata"),
+        face_count: row.get("face_count"),
+    }
+}
+
+async fn get_person_by_id<C: GenericClient>(
+    client: &C,
+    id: i64,
+) -> Result<PersonResponse, Error> {
+    let row = client
+        .query_one(
+            format!(
+                "SELECT {}, (SELECT count(*) FROM face_embeddings f WHERE f.person_id = p.id) AS face_count FROM persons p WHERE p.id = $1",
+                PERSON_COLUMNS
+            )
+            .as_str(),
+            &[&id],
+        )
+        .await?;
+    Ok(person_from_row(&row))
+}
+
+fn bad_request(message: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(GenericResponse {
+        status: 400,
+        message,
+    })
+}
+
+fn not_found(message: String) -> HttpResponse {
+    HttpResponse::NotFound().json(GenericResponse {
+        status: 404,
+        message,
+    })
+}
+
+/// metadata has to be an object so it can be extended later on
+fn invalid_metadata(metadata: &Option<Value>) -> Option<HttpResponse> {
+    match metadata {
+        Some(metadata) if !metadata.is_object() => {
+            Some(bad_request(String::from("metadata must be a json object")))
+        }
+        _ => None,
+    }
+}
+
+/// creates a person without any embedding, faces are added with `/post_face_vec`
+#[post("/persons")]
+pub async fn create_person(
+    pool: web::Data<Pool>,
+    form: web::Json<CreatePersonRequest>,
+) -> actix_web::Result<HttpResponse> {
+    if let Some(response) = invalid_metadata(&form.metadata) {
+        return Ok(response);
+    }
+    let client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let metadata = form.metadata.clone().unwrap_or_else(|| json!({}));
+    let row = client
+        .query_one(
+            "INSERT INTO persons (person_uuid, name, gender, collection, metadata) VALUES (gen_random_uuid()::text, $1, $2, $3, $4) RETURNING id",
+            &[&form.name, &form.gender, &form.collection, &metadata],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let person = get_person_by_id(&client, row.get("id"))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Created().json(person))
+}
+
+#[post("/get_person")]
+pub async fn get_person(
+    pool: web::Data<Pool>,
+    form: web::Json<GetPersonRequest>,
+) -> actix_web::Result<HttpResponse> {
+    let client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let row = client
+        .query_opt("SELECT id FROM persons WHERE person_uuid = $1", &[&form.person_uuid])
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let Some(row) = row else {
+        return Ok(not_found(String::from("no person with the given person_uuid")));
+    };
+    let id: i64 = row.get("id");
+    let person = get_person_by_id(&client, id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let face_uuids = client
+        .query(
+            "SELECT DISTINCT face_uuid FROM face_embeddings WHERE person_id = $1 ORDER BY face_uuid",
+            &[&id],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?
+        .iter()
+        .map(|row| row.get("face_uuid"))
+        .collect();
+    Ok(HttpResponse::Ok().json(PersonDetailResponse { person, face_uuids }))
+}
+
+#[post("/persons/merge")]
+pub async fn merge_persons(
+    pool: web::Data<Pool>,
+    form: web::Json<MergePersonsRequest>,
+) -> actix_web::Result<HttpResponse> {
+    if form.merge_person_uuids.is_empty() {
+        return Ok(bad_request(String::from("merge_person_uuids is empty")));
+    }
+    if form.merge_person_uuids.contains(&form.person_uuid) {
+        return Ok(bad_request(String::from("a person cannot be merged into itself")));
+    }
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let transaction = client.transaction().await.map_err(ErrorInternalServerError)?;
+    // locked so a concurrent merge / split cannot move faces to a deleted person
+    let target = transaction
+        .query_opt(
+            "SELECT id FROM persons WHERE person_uuid = $1 FOR UPDATE",
+            &[&form.person_uuid],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let Some(target) = target else {
+        return Ok(not_found(String::from("no person with the given person_uuid")));
+    };
+    let target_id: i64 = target.get("id");
+    let sources = transaction
+        .query(
+            "SELECT id, person_uuid FROM persons WHERE person_uuid = ANY($1) FOR UPDATE",
+            &[&form.merge_person_uuids],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let missing: Vec<&String> = form
+        .merge_person_uuids
+        .iter()
+        .filter(|uuid| !sources.iter().any(|row| row.get::<_, String>("person_uuid") == **uuid))
+        .collect();
+    if !missing.is_empty() {
+        return Ok(not_found(format!("unknown persons: {:?}", missing)));
+    }
+    let source_ids: Vec<i64> = sources.iter().map(|row| row.get("id")).collect();
+    transaction
+        .execute(
+            "UPDATE face_embeddings SET person_id = $1 WHERE person_id = ANY($2)",
+            &[&target_id, &source_ids],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    transaction
+        .execute("DELETE FROM persons WHERE id = ANY($1)", &[&source_ids])
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let person = get_person_by_id(&transaction, target_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    transaction.commit().await.map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(person))
+}
+
+/// returns the new person
+#[post("/persons/split")]
+pub async fn split_person(
+    pool: web::Data<Pool>,
+    form: web::Json<SplitPersonRequest>,
+) -> actix_web::Result<HttpResponse> {
+    if form.face_uuids.is_empty() {
+        return Ok(bad_request(String::from("face_uuids is empty")));
+    }
+    if let Some(response) = invalid_metadata(&form.metadata) {
+        return Ok(response);
+    }
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let transaction = client.transaction().await.map_err(ErrorInternalServerError)?;
+    let source = transaction
+        .query_opt(
+            "SELECT id, name, gender, collection, metadata FROM persons WHERE person_uuid = $1 FOR UPDATE",
+            &[&form.person_uuid],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let Some(source) = source else {
+        return Ok(not_found(String::from("no person with the given person_uuid")));
+    };
+    let source_id: i64 = source.get("id");
+    let owned: Vec<String> = transaction
+        .query(
+            "SELECT DISTINCT face_uuid FROM face_embeddings WHERE person_id = $1 AND face_uuid = ANY($2)",
+            &[&source_id, &form.face_uuids],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?
+        .iter()
+        .map(|row| row.get("face_uuid"))
+        .collect();
+    let foreign: Vec<&String> = form
+        .face_uuids
+        .iter()
+        .filter(|uuid| !owned.contains(uuid))
+        .collect();
+    if !foreign.is_empty() {
+        return Ok(bad_request(format!(
+            "faces not belonging to the person: {:?}",
+            foreign
+        )));
+    }
+    let name = form.name.clone().or_else(|| source.get("name"));
+    let gender = form.gender.or_else(|| source.get("gender"));
+    let collection = form.collection.clone().or_else(|| source.get("collection"));
+    let metadata = form.metadata.clone().unwrap_or_else(|| source.get("metadata"));
+    let row = transaction
+        .query_one(
+            "INSERT INTO persons (person_uuid, name, gender, collection, metadata) VALUES (gen_random_uuid()::text, $1, $2, $3, $4) RETURNING id",
+            &[&name, &gender, &collection, &metadata],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let person_id: i64 = row.get("id");
+    transaction
+        .execute(
+            "UPDATE face_embeddings SET person_id = $1 WHERE person_id = $2 AND face_uuid = ANY($3)",
+            &[&person_id, &source_id, &form.face_uuids],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let person = get_person_by_id(&transaction, person_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    transaction.commit().await.map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Created().json(person))
+}
+
+/// similarity search ranked per person instead of per face.
+/// the nearest faces pick the candidate persons (index friendly), each candidate is
+/// then scored over all of its embeddings (of `collection` if set) with [PersonAggregation]
+#[post("/search_persons")]
+pub async fn search_persons(
+    pool: web::Data<Pool>,
+    form: web::Json<SearchPersonsRequest>,
+) -> actix_web::Result<HttpResponse> {
+    if form.face_embedding.len() != 512 {
+        return Ok(bad_request(String::from(
+            "invalid vector dimension , input vector must be exactly 512 long!",
+        )));
+    }
+    if form.count < 1 {
+        return Ok(bad_request(format!("count must be at least 1, got {}", form.count)));
+    }
+    if let Some(x) = form.min_similarity.filter(|x| !(-1.0..=1.0).contains(x)) {
+        return Ok(bad_request(format!(
+            "min_similarity must be between -1 and 1, got {}",
+            x
+        )));
+    }
+    if let Err(message) = form.tuning.validate() {
+        return Ok(bad_request(message));
+    }
+    let aggregate = match form.aggregation {
+        PersonAggregation::Max => "max",
+        PersonAggregation::Mean => "avg",
+    };
+    let sql = format!(
+        "WITH nearest AS (
+            SELECT person_id FROM face_embeddings
+            WHERE person_id IS NOT NULL AND ($3::varchar IS NULL OR collection = $3)
+            ORDER BY embedding <=> $1 LIMIT $2
+        )
+        SELECT {columns}, count(*) AS face_count, {aggregate}(1 - (f.embedding <=> $1)) AS cosine_similarity
+        FROM persons p JOIN face_embeddings f ON f.person_id = p.id
+        WHERE p.id IN (SELECT person_id FROM nearest)
+            AND ($3::varchar IS NULL OR f.collection = $3)
+        GROUP BY p.id
+        HAVING $4::float8 IS NULL OR {aggregate}(1 - (f.embedding <=> $1)) >= $4
+        ORDER BY cosine_similarity DESC LIMIT $5",
+        columns = PERSON_COLUMNS,
+        aggregate = aggregate
+    );
+    let candidates = (form.count * PERSON_CANDIDATES_PER_RESULT).min(MAX_PERSON_CANDIDATES);
+    let face_embedding = pgvector::Vector::from(form.face_embedding.to_owned());
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let transaction = client.transaction().await.map_err(ErrorInternalServerError)?;
+    apply_search_tuning(&transaction, &form.tuning)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let rows = transaction
+        .query(
+            sql.as_str(),
+            &[
+                &face_embedding,
+                &candidates,
+                &form.collection,
+                &form.min_similarity,
+                &form.count,
+            ],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    transaction.commit().await.map_err(ErrorInternalServerError)?;
+    let matches: Vec<PersonMatchResponse> = rows
+        .iter()
+        .map(|row| PersonMatchResponse {
+            person: person_from_row(row),
+            cosine_similarity: row.get("cosine_similarity"),
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(matches))
+}
@@ -1,18 +1,130 @@
 use actix_multipart::form::MultipartForm;
+use actix_web::error::ErrorInternalServerError;
 use actix_web::{get, post, web};
 use actix_web::{HttpRequest, HttpResponse};
 use anyhow::{Error, Result};
+use deadpool_postgres::tokio_postgres::types::ToSql;
+use deadpool_postgres::tokio_postgres::Row;
 use deadpool_postgres::{GenericClient, Pool};
 use pgvector::Vector;
-use soma_db_api::handlers::face::{
-    GetFaceByUuidRequest, GetFaceDetailResponse, 
-    GetSimilarFacesByUuidRequest, GetSimilarFacesByUuidResponse,
+
+use crate::handlers::face::{
+    FaceSearchFilter, GetFaceByUuidRequest, GetFaceDetailResponse,
+    GetSimilarFacesByEmbeddingRequest, GetSimilarFacesByUuidRequest,
+    GetSimilarFacesByUuidResponse, SearchTuning,
 };
-use soma_db_api::handlers::GenericResponse;
+use crate::handlers::GenericResponse;
+use crate::utils::vector_index::apply_search_tuning;
+
+/// reads a `face_embeddings` row selected with `FACE_COLUMNS`
+pub(crate) fn face_from_row(row: &Row) -> GetFaceDetailResponse {
+    let embedding: pgvector::Vector = row.get("embedding");
+    GetFaceDetailResponse {
+        id: row.get("id"),
+        name: row.get("name"),
+        face_uuid: row.get("face_uuid"),
+        gender: row.get("gender"),
+        embedding: embedding.to_vec(),
+        collection: row.get("collection"),
+        person_uuid: row.get("person_uuid"),
+    }
+}
 
-use crate::handlers::face::GetSimilarFacesByEmbeddingRequest;
+pub(crate) const FACE_COLUMNS: &str = "id, name, gender, embedding, face_uuid, collection, (SELECT person_uuid FROM persons WHERE persons.id = face_embeddings.person_id) AS person_uuid";
 
+/// similarity search compiled from a [FaceSearchFilter],
+/// every value is a bind parameter, `$1` is the query vector
+struct SimilarityQuery {
+    clauses: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
 
+impl SimilarityQuery {
+    fn new(face_embedding: Vector, filter: &FaceSearchFilter) -> SimilarityQuery {
+        let mut query = SimilarityQuery {
+            clauses: vec![],
+            params: vec![Box::new(face_embedding)],
+        };
+        // on the distance rather than the similarity so a vector index can still be used
+        if let Some(min_similarity) = filter.min_similarity {
+            query.push("(embedding <=> $1) <=", 1.0 - min_similarity);
+        }
+        if let Some(name) = &filter.name {
+            query.push("name =", name.clone());
+        }
+        if let Some(gender) = filter.gender {
+            query.push("gender =", gender);
+        }
+        if let Some(collection) = &filter.collection {
+            query.push("collection =", collection.clone());
+        }
+        if let Some(face_uuid) = &filter.exclude_face_uuid {
+            query.push("face_uuid <>", face_uuid.clone());
+        }
+        query
+    }
+
+    /// adds `<lhs> $n` with `value` bound to `$n`
+    fn push<T: ToSql + Sync + Send + 'static>(&mut self, lhs: &str, value: T) {
+        self.params.push(Box::new(value));
+        self.clauses.push(format!("{} ${}", lhs, self.params.len()));
+    }
+
+    /// the nearest `count` faces, most similar first.
+    /// runs in its own transaction so the `tuning` settings do not leak to the pooled connection
+    async fn run(
+        mut self,
+        client: &mut deadpool_postgres::Client,
+        count: i64,
+        tuning: &SearchTuning,
+    ) -> Result<Vec<GetSimilarFacesByUuidResponse>, deadpool_postgres::tokio_postgres::Error> {
+        self.params.push(Box::new(count));
+        let where_clause = match self.clauses.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", self.clauses.join(" AND ")),
+        };
+        let sql = format!(
+            "SELECT {}, 1 - (embedding <=> $1) AS cosine_similarity FROM face_embeddings {} ORDER BY embedding <=> $1 LIMIT ${}",
+            FACE_COLUMNS,
+            where_clause,
+            self.params.len()
+        );
+        let params: Vec<&(dyn ToSql + Sync)> = self
+            .params
+            .iter()
+            .map(|x| x.as_ref() as &(dyn ToSql + Sync))
+            .collect();
+        let transaction = client.transaction().await?;
+        apply_search_tuning(&transaction, tuning).await?;
+        let rows = transaction.query(sql.as_str(), &params).await?;
+        transaction.commit().await?;
+        Ok(rows
+            .iter()
+            .map(|row| GetSimilarFacesByUuidResponse {
+                face: face_from_row(row),
+                cosine_similarity: row.get("cosine_similarity"),
+            })
+            .collect())
+    }
+}
+
+fn invalid_search(
+    count: i64,
+    filter: &FaceSearchFilter,
+    tuning: &SearchTuning,
+) -> Option<HttpResponse> {
+    let message = match filter.min_similarity {
+        _ if count < 1 => format!("count must be at least 1, got {}", count),
+        Some(x) if !(-1.0..=1.0).contains(&x) => {
+            format!("min_similarity must be between -1 and 1, got {}", x)
+        }
+        _ => tuning.validate().err()?,
+    };
+    Some(HttpResponse::BadRequest().json(GenericResponse {
+        status: 400,
+        message,
+    }))
+}
 
 #[post("/get_face_by_uuid")]
 pub async fn get_face_from_uuid(
@@ -22,22 +134,10 @@ pub async fn get_face_from_uuid(
 ) -> actix_web::Result<HttpResponse> {
     let client = pool.get().await.unwrap();
 
-    // TODO: REFACTOR GET FACE 
-    let rows = client.query("SELECT id, name, gender,embedding , face_uuid FROM face_embeddings WHERE face_uuid = $1", &[&form.face_uuid]).await.unwrap();
+    // TODO: REFACTOR GET FACE
+    let rows = client.query(format!("SELECT {} FROM face_embeddings WHERE face_uuid = $1", FACE_COLUMNS).as_str(), &[&form.face_uuid]).await.unwrap();
     if rows.len() > 0 {
-        let results: Vec<GetFaceDetailResponse> = rows
-            .into_iter()
-            .map(|row| {
-                let embedding: pgvector::Vector = row.get("embedding");
-                GetFaceDetailResponse {
-                    id: row.get("id"),
-                    name: row.get("name"),
-                    face_uuid: row.get("face_uuid"),
-                    gender: row.get("gender"),
-                    embedding: embedding.to_vec(),
-                }
-            })
-            .collect();
+        let results: Vec<GetFaceDetailResponse> = rows.iter().map(face_from_row).collect();
         Ok(HttpResponse::Ok().json(results))
     } else {
         Ok(HttpResponse::Ok().json(GenericResponse {
@@ -52,44 +152,17 @@ pub async fn get_similar_faces_by_embedding(
     pool: web::Data<Pool>,
     form: web::Json<GetSimilarFacesByEmbeddingRequest>
 ) -> actix_web::Result<HttpResponse> {
-    let client = pool.get().await.unwrap();
-    let face_embedding = pgvector::Vector::from(form.face_embedding.to_owned());
-    let rows = client.query("SELECT id,name,gender,embedding,face_uuid, 1 - (embedding <=> $1) AS cosine_similarity FROM face_embeddings ORDER BY cosine_similarity DESC LIMIT $2", &[&face_embedding, &form.count]).await.unwrap();
-    let similar_faces_results = {  
-        if rows.len() > 0 {
-
-            let results: Vec<GetSimilarFacesByUuidResponse> = rows
-                    .into_iter()
-                    .map(|row| {
-                        let embedding: pgvector::Vector = row.get("embedding");
-                        let face = GetFaceDetailResponse {
-                            id: row.get("id"),
-                            name: row.get("name"),
-                            face_uuid: row.get("face_uuid"),
-                            gender: row.get("gender"),
-                            embedding: embedding.to_vec(),
-                        };
-                        let cosine_sim = row.get("cosine_similarity");
-                        GetSimilarFacesByUuidResponse {
-                            face: face,
-                            cosine_similarity: cosine_sim,
-                        }
-                    })
-                    .collect();
-
-                results
-        } else {
-            let _res: Vec<GetSimilarFacesByUuidResponse> = vec![];
-            _res
-        }
-    };
-    if similar_faces_results.len() > 0 {
-        Ok(HttpResponse::Ok().json(similar_faces_results))
-    } else {
-        let results: Vec<GetSimilarFacesByUuidResponse> = vec![];
-        // just send a empty vec for now 
-        Ok(HttpResponse::Ok().json(results))
+    if let Some(response) = invalid_search(form.count, &form.filter, &form.tuning) {
+        return Ok(response);
     }
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
+    let face_embedding = pgvector::Vector::from(form.face_embedding.to_owned());
+    // just send a empty vec when nothing matches
+    let similar_faces_results = SimilarityQuery::new(face_embedding, &form.filter)
+        .run(&mut client, form.count, &form.tuning)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(similar_faces_results))
 }
 
 #[post("/get_similar_faces_by_uuid")]
@@ -98,64 +171,30 @@ pub async fn get_similar_faces_by_uuid(
     form: web::Json<GetSimilarFacesByUuidRequest>,
     _: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
-    let client = pool.get().await.unwrap();
+    if let Some(response) = invalid_search(form.count, &form.filter, &form.tuning) {
+        return Ok(response);
+    }
+    let mut client = pool.get().await.map_err(ErrorInternalServerError)?;
     // get face embedding first
-    let rows = client.query("SELECT id, name, gender,embedding , face_uuid FROM face_embeddings WHERE face_uuid = $1", &[&form.face_uuid]).await.unwrap();
-    let face_query_result: Vec<GetFaceDetailResponse> = {
-        if rows.len() > 0 {
-            let results: Vec<GetFaceDetailResponse> = rows
-                .into_iter()
-                .map(|row| {
-                    let embedding: pgvector::Vector = row.get("embedding");
-                    GetFaceDetailResponse {
-                        id: row.get("id"),
-                        name: row.get("name"),
-                        face_uuid: row.get("face_uuid"),
-                        gender: row.get("gender"),
-                        embedding: embedding.to_vec(),
-                    }
-                })
-                .collect();
-            results
-        } else {
-            let results: Vec<GetFaceDetailResponse> = vec![];
-            results
-        }
+    let rows = client
+        .query(
+            "SELECT embedding FROM face_embeddings WHERE face_uuid = $1 LIMIT 1",
+            &[&form.face_uuid],
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    let face_embedding: pgvector::Vector = match rows.first() {
+        Some(row) => row.get("embedding"),
+        // just send a empty vec for now
+        None => return Ok(HttpResponse::Ok().json(Vec::<GetSimilarFacesByUuidResponse>::new())),
     };
-    let similar_faces_results = {
-        if face_query_result.len() > 0 {
-            let face_embedding = pgvector::Vector::from(face_query_result[0].embedding.to_owned());
-            let rows = client.query("SELECT id,name,gender,embedding,face_uuid, 1 - (embedding <=> $1) AS cosine_similarity FROM face_embeddings ORDER BY cosine_similarity DESC LIMIT $2", &[&face_embedding, &form.count]).await.unwrap();
-            let results: Vec<GetSimilarFacesByUuidResponse> = rows
-                .into_iter()
-                .map(|row| {
-                    let embedding: pgvector::Vector = row.get("embedding");
-                    let face = GetFaceDetailResponse {
-                        id: row.get("id"),
-                        name: row.get("name"),
-                        face_uuid: row.get("face_uuid"),
-                        gender: row.get("gender"),
-                        embedding: embedding.to_vec(),
-                    };
-                    let cosine_sim = row.get("cosine_similarity");
-                    GetSimilarFacesByUuidResponse {
-                        face: face,
-                        cosine_similarity: cosine_sim,
-                    }
-                })
-                .collect();
-            results
-        } else {
-            let _res: Vec<GetSimilarFacesByUuidResponse> = vec![];
-            _res
-        }
-    };
-    if similar_faces_results.len() > 0 {
-        Ok(HttpResponse::Ok().json(similar_faces_results))
-    } else {
-        let results: Vec<GetSimilarFacesByUuidResponse> = vec![];
-        // just send a empty vec for now 
-        Ok(HttpResponse::Ok().json(results))
+    let mut filter = form.filter.clone();
+    if form.exclude_self {
+        filter.exclude_face_uuid = Some(form.face_uuid.clone());
     }
+    let similar_faces_results = SimilarityQuery::new(face_embedding, &filter)
+        .run(&mut client, form.count, &form.tuning)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(similar_faces_results))
 }
-
@@ -4,33 +4,108 @@ mod utils;
 
 use actix_web::http::header::ContentType;
 use actix_web::{middleware::Logger, web, App, HttpRequest, HttpResponse, HttpServer};
+use clap::Parser;
 use dotenvy::dotenv;
+use operators::admin::{get_vector_index, rebuild_index};
+use operators::identify::{identify_face, IdentifyConfig};
 use operators::index;
 use operators::insertion::insert_face_vector;
+use operators::persons::{create_person, get_person, merge_persons, search_persons, split_person};
 use operators::queries::{get_face_from_uuid, get_similar_faces_by_embedding, get_similar_faces_by_uuid};
 use serde::{Deserialize, Serialize};
 use std::env;
-use utils::db_utils::init_pool;
+use utils::db_utils::{create_pool, init_pool};
+use utils::migrations::{migration_status, run_migrations};
+use utils::vector_index::{ensure_vector_index, VectorIndexConfig};
+
+#[derive(clap::Parser)]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// lists the schema migrations and whether they are applied, changes nothing
+    Status,
+    /// applies the pending migrations and exits without starting the server
+    Migrate,
+}
+
+/// `status` / `migrate` subcommands, the server itself applies migrations on startup
+async fn run_command(command: Command) -> std::io::Result<()> {
+    let pool = create_pool();
+    let mut client = pool.get().await.unwrap();
+    match command {
+        Command::Status => {
+            let status = migration_status(&client).await.unwrap();
+            let pending = status.iter().filter(|x| x.applied_at.is_none()).count();
+            for migration in status {
+                let state = match (&migration.applied_at, migration.known) {
+                    (None, _) => String::from("pending"),
+                    (Some(applied_at), true) => format!("applied {}", applied_at),
+                    (Some(applied_at), false) => format!("applied {} (unknown to this build)", applied_at),
+                };
+                println!("{:04} {:<40} {}", migration.version, migration.name, state);
+            }
+            println!("{} pending", pending);
+        }
+        Command::Migrate => {
+            let applied = run_migrations(&mut client).await.unwrap();
+            for migration in &applied {
+                println!("applied migration {:04} {}", migration.version, migration.name);
+            }
+            println!("{} applied", applied.len());
+        }
+    }
+    Ok(())
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    let args = CliArgs::parse();
     std::env::set_var("RUST_LOG", "actix_web=debug");
     env_logger::init();
     dotenv().ok();
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
     let SERVER_ADDRESS = env::var("SERVER_ADDRESS").expect("cannot read server addr");
     let SERVER_PORT = env::var("SERVER_PORT").expect("cannot read server port");
     let bind_addr = format!("{}:{}", SERVER_ADDRESS, SERVER_PORT);
     let pool = web::Data::new(init_pool().await?);
+    let identify_config = web::Data::new(IdentifyConfig::from_env());
+    let index_config = VectorIndexConfig::from_env();
+    index_config
+        .validate()
+        .unwrap_or_else(|e| panic!("invalid vector index config: {}", e));
+    ensure_vector_index(&pool.get().await.unwrap(), &index_config)
+        .await
+        .unwrap();
+    let index_config = web::Data::new(index_config);
     utils::print_splash();
     println!("starting server on {:?}", &bind_addr);
     HttpServer::new(move || {
         App::new()
             .app_data(pool.clone())
+            .app_data(identify_config.clone())
+            .app_data(index_config.clone())
             .service(web::scope("/info").route("", web::get().to(index)))
             .service(insert_face_vector)
             .service(get_face_from_uuid)
             .service(get_similar_faces_by_uuid)
             .service(get_similar_faces_by_embedding)
+            .service(identify_face)
+            .service(create_person)
+            .service(get_person)
+            .service(merge_persons)
+            .service(split_person)
+            .service(search_persons)
+            .service(
+                web::scope("/admin")
+                    .service(get_vector_index)
+                    .service(rebuild_index),
+            )
             .wrap(Logger::default())
     })
     .bind(&bind_addr)?
@@ -1,15 +1,17 @@
 use anyhow::{Error, Result};
-use deadpool_postgres::{GenericClient, Manager, Pool};
+use deadpool_postgres::{Manager, Pool};
 use dotenvy::dotenv;
 use postgres::NoTls;
 use std::env;
 
+use super::migrations::run_migrations;
+
 pub async fn insert_one_face_vector(pool: Pool) -> Result<()> {
     Ok(())
 }
 
-/// gets a mf pool , creates tables if it not already there.
-pub async fn init_pool() -> std::io::Result<Pool> {
+/// gets a mf pool, no schema changes.
+pub fn create_pool() -> Pool {
     dotenv().ok();
     let DB_HOST = env::var("DB_HOST").expect("cannot read DB_HOST");
     let DB_PORT = env::var("DB_PORT").expect("cannot read DB_PORT");
@@ -23,34 +25,18 @@ pub async fn init_pool() -> std::io::Result<Pool> {
     poolcfg.host = Some(DB_HOST);
     poolcfg.dbname = Some(DB_DATABASE);
     poolcfg.port = Some(DB_PORT.parse::<u16>().unwrap());
-    let pool: Pool = poolcfg.create_pool(None, NoTls).unwrap();
-    let _get_pool = pool.get().await.unwrap();
-    _get_pool
-        .batch_execute(
-            "
-    CREATE TABLE IF NOT EXISTS frame_tags (
-                                        id bigserial PRIMARY KEY,
-										frame_name varchar(512),
-										original_source varchar(512),
-										orignial_type int,
-                                        face_count int,
-										frame_tags varchar(4096));",
-        )
-        .await
-        .unwrap();
+    poolcfg.create_pool(None, NoTls).unwrap()
+}
 
-    _get_pool
-        .batch_execute(
-            "
-        CREATE EXTENSION IF NOT EXISTS vector;
-                CREATE TABLE IF NOT EXISTS face_embeddings (id bigserial PRIMARY KEY, 
-                                               name varchar(255), 
-                                               face_uuid varchar(512) NOT NULL,
-                                               gender int ,
-                                               embedding vector(512));
-",
-        )
+/// gets a mf pool, applies the pending migrations.
+pub async fn init_pool() -> std::io::Result<Pool> {
+    let pool = create_pool();
+    let mut client = pool.get().await.unwrap();
+    let applied = run_migrations(&mut client)
         .await
-        .unwrap();
+        .expect("cannot apply migrations");
+    for migration in applied {
+        println!("applied migration {:04} {}", migration.version, migration.name);
+    }
     Ok(pool)
 }
@@ -0,0 +1,130 @@
+//! embedded schema migrations.
+//!
+//! the files of `migrations/` are compiled in through [MIGRATIONS] and applied once,
+//! in order, each one in its own transaction together with its `schema_migrations` row.
+//! an applied migration must never be edited, add a new file instead
+
+use deadpool_postgres::tokio_postgres::{Client, Error};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+/// every migration, ordered by version. append only
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_initial"),
+    migration!(2, "0002_face_embeddings_collection"),
+    migration!(3, "0003_frame_tags_original_type"),
+    migration!(4, "0004_persons"),
+];
+
+/// advisory lock key, so instances starting together do not apply the same migration twice
+const MIGRATIONS_LOCK: i64 = 0x736f_6d61;
+
+const CREATE_MIGRATIONS_TABLE: &str = "
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version bigint PRIMARY KEY,
+    name varchar(255) NOT NULL,
+    applied_at timestamptz NOT NULL DEFAULT now()
+);";
+
+/// applies the pending migrations, returns the ones applied by this call
+pub async fn run_migrations(client: &mut Client) -> Result<Vec<&'static Migration>, Error> {
+    client.batch_execute(CREATE_MIGRATIONS_TABLE).await?;
+    let mut applied = vec![];
+    for migration in MIGRATIONS {
+        let transaction = client.transaction().await?;
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATIONS_LOCK])
+            .await?;
+        // checked under the lock, another instance may have just applied it
+        let done = transaction
+            .query_opt(
+                "SELECT 1 FROM schema_migrations WHERE version = $1",
+                &[&migration.version],
+            )
+            .await?
+            .is_some();
+        if done {
+            continue;
+        }
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    /// `None` while pending
+    pub applied_at: Option<String>,
+    /// false for versions in the database this build does not know about (applied by a newer build)
+    pub known: bool,
+}
+
+/// state of every known migration plus any unknown one found in the database, changes nothing
+pub async fn migration_status(client: &Client) -> Result<Vec<MigrationStatus>, Error> {
+    let table_exists = client
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?
+        .get::<_, bool>(0);
+    let rows = match table_exists {
+        true => {
+            client
+                .query(
+                    "SELECT version, name, applied_at::text AS applied_at FROM schema_migrations ORDER BY version",
+                    &[],
+                )
+                .await?
+        }
+        false => vec![],
+    };
+    let applied: Vec<(i64, String, String)> = rows
+        .iter()
+        .map(|row| (row.get("version"), row.get("name"), row.get("applied_at")))
+        .collect();
+    let mut status: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            name: migration.name.to_string(),
+            applied_at: applied
+                .iter()
+                .find(|(version, _, _)| *version == migration.version)
+                .map(|(_, _, applied_at)| applied_at.clone()),
+            known: true,
+        })
+        .collect();
+    for (version, name, applied_at) in applied {
+        if !MIGRATIONS.iter().any(|migration| migration.version == version) {
+            status.push(MigrationStatus {
+                version,
+                name,
+                applied_at: Some(applied_at),
+                known: false,
+            });
+        }
+    }
+    status.sort_by_key(|x| x.version);
+    Ok(status)
+}
@@ -1,4 +1,6 @@
 pub mod db_utils;
+pub mod migrations;
+pub mod vector_index;
 
 const splash: &str = r#"
              .▄▄ ·       • ▌ ▄ ·.  ▄▄▄·     
@@ -0,0 +1,190 @@
+//! vector index on `face_embeddings.embedding`.
+//!
+//! searches order by `embedding <=> $1` (cosine distance), so the index is built
+//! with `vector_cosine_ops`, an index on another operator class would never be used.
+//! the kind and build parameters come from `VECTOR_INDEX`, `HNSW_M`,
+//! `HNSW_EF_CONSTRUCTION` and `IVFFLAT_LISTS`, the index is created on startup
+//! if it does not exist and can be rebuilt with `POST /admin/rebuild_index`
+
+use crate::handlers::face::SearchTuning;
+use deadpool_postgres::tokio_postgres::{Client, Error, Transaction};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+pub const VECTOR_INDEX_NAME: &str = "face_embeddings_embedding_idx";
+
+/// operator class matching the `<=>` operator of the queries
+const COSINE_OPS: &str = "vector_cosine_ops";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    /// better recall / speed tradeoff, slower to build, works on an empty table
+    Hnsw,
+    /// faster to build, the lists are computed from the rows present at build time
+    /// so it should be rebuilt once the table is filled
+    IvfFlat,
+    /// exact scans only
+    None,
+}
+
+impl IndexKind {
+    pub fn parse(value: &str) -> Option<IndexKind> {
+        match value.to_lowercase().as_str() {
+            "hnsw" => Some(IndexKind::Hnsw),
+            "ivfflat" => Some(IndexKind::IvfFlat),
+            "none" => Some(IndexKind::None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct VectorIndexConfig {
+    pub kind: IndexKind,
+    /// hnsw: max connections per node
+    pub m: u32,
+    /// hnsw: candidate list size while building
+    pub ef_construction: u32,
+    /// ivfflat: number of lists, around rows / 1000 up to 1M rows
+    pub lists: u32,
+}
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        VectorIndexConfig {
+            kind: IndexKind::Hnsw,
+            m: 16,
+            ef_construction: 64,
+            lists: 100,
+        }
+    }
+}
+
+impl VectorIndexConfig {
+    pub fn from_env() -> VectorIndexConfig {
+        let default = VectorIndexConfig::default();
+        let read = |key: &str, default: u32| {
+            env::var(key)
+                .ok()
+                .map(|x| x.parse::<u32>().unwrap_or_else(|_| panic!("invalid {} `{}`", key, x)))
+                .unwrap_or(default)
+        };
+        let kind = env::var("VECTOR_INDEX")
+            .ok()
+            .map(|x| IndexKind::parse(&x).unwrap_or_else(|| panic!("invalid VECTOR_INDEX `{}`, use hnsw, ivfflat or none", x)))
+            .unwrap_or(default.kind);
+        VectorIndexConfig {
+            kind,
+            m: read("HNSW_M", default.m),
+            ef_construction: read("HNSW_EF_CONSTRUCTION", default.ef_construction),
+            lists: read("IVFFLAT_LISTS", default.lists),
+        }
+    }
+
+    /// the build parameters are inlined (ddl takes no bind parameters), so they are checked here
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kind {
+            IndexKind::Hnsw if !(2..=100).contains(&self.m) => {
+                Err(format!("m must be between 2 and 100, got {}", self.m))
+            }
+            IndexKind::Hnsw if self.ef_construction < 2 * self.m || self.ef_construction > 1000 => Err(format!(
+                "ef_construction must be between 2 * m and 1000, got {}",
+                self.ef_construction
+            )),
+            IndexKind::IvfFlat if !(1..=32768).contains(&self.lists) => {
+                Err(format!("lists must be between 1 and 32768, got {}", self.lists))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `CREATE INDEX` statement for `name`, `None` for [IndexKind::None]
+    fn create_sql(&self, name: &str, concurrently: bool) -> Option<String> {
+        let using = match self.kind {
+            IndexKind::Hnsw => format!(
+                "hnsw (embedding {}) WITH (m = {}, ef_construction = {})",
+                COSINE_OPS, self.m, self.ef_construction
+            ),
+            IndexKind::IvfFlat => format!(
+                "ivfflat (embedding {}) WITH (lists = {})",
+                COSINE_OPS, self.lists
+            ),
+            IndexKind::None => return None,
+        };
+        let concurrently = match concurrently {
+            true => "CONCURRENTLY ",
+            false => "",
+        };
+        Some(format!(
+            "CREATE INDEX {}IF NOT EXISTS {} ON face_embeddings USING {}",
+            concurrently, name, using
+        ))
+    }
+}
+
+/// creates the configured index if there is none yet,
+/// an existing index is kept even if its kind differs (rebuild it to switch)
+pub async fn ensure_vector_index(
+    client: &Client,
+    config: &VectorIndexConfig,
+) -> Result<(), Error> {
+    if let Some(sql) = config.create_sql(VECTOR_INDEX_NAME, false) {
+        client.batch_execute(&sql).await?;
+    }
+    Ok(())
+}
+
+/// builds the new index next to the old one (concurrently, writes keep going)
+/// and swaps them, [IndexKind::None] just drops the index
+pub async fn rebuild_vector_index(
+    client: &Client,
+    config: &VectorIndexConfig,
+) -> Result<(), Error> {
+    let staging = format!("{}_new", VECTOR_INDEX_NAME);
+    client
+        .batch_execute(&format!("DROP INDEX CONCURRENTLY IF EXISTS {}", staging))
+        .await?;
+    match config.create_sql(&staging, true) {
+        Some(sql) => {
+            client.batch_execute(&sql).await?;
+            client
+                .batch_execute(&format!(
+                    "BEGIN; DROP INDEX IF EXISTS {name}; ALTER INDEX {staging} RENAME TO {name}; COMMIT;",
+                    name = VECTOR_INDEX_NAME,
+                    staging = staging
+                ))
+                .await
+        }
+        None => {
+            client
+                .batch_execute(&format!("DROP INDEX CONCURRENTLY IF EXISTS {}", VECTOR_INDEX_NAME))
+                .await
+        }
+    }
+}
+
+/// `indexdef` of every index on `face_embeddings.embedding`
+pub async fn vector_index_definitions(client: &Client) -> Result<Vec<String>, Error> {
+    let rows = client
+        .query(
+            "SELECT indexdef FROM pg_indexes WHERE tablename = 'face_embeddings' AND indexdef LIKE '%(embedding%'",
+            &[],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get("indexdef")).collect())
+}
+
+/// applies the per request `ef_search` / `probes`, only for the current transaction
+pub async fn apply_search_tuning(tx: &Transaction<'_>, tuning: &SearchTuning) -> Result<(), Error> {
+    // `SET LOCAL` takes no bind parameters, `set_config(.., true)` is the same thing
+    if let Some(ef_search) = tuning.ef_search {
+        tx.query("SELECT set_config('hnsw.ef_search', $1, true)", &[&ef_search.to_string()])
+            .await?;
+    }
+    if let Some(probes) = tuning.probes {
+        tx.query("SELECT set_config('ivfflat.probes', $1, true)", &[&probes.to_string()])
+            .await?;
+    }
+    Ok(())
+}
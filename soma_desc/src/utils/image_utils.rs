@@ -4,12 +4,13 @@ use base64::{
     Engine as _,
 };
 use image::{self, DynamicImage};
+use soma_core::image_ingest::{self, IngestConfig};
 
 ///
 /// shinji decode the fucking b64 STRING
+/// (same limits and `data:` url handling as the face api, see [soma_core::image_ingest])
 pub fn decode_base64(b64_image: &str) -> Result<image::DynamicImage> {
-    let base64_decode: Vec<u8> = general_purpose::STANDARD.decode(b64_image)?;
-    let _img = image::load_from_memory(&base64_decode.as_slice())?;
+    let _img = image_ingest::decode_base64(b64_image, &IngestConfig::default())?;
     Ok(_img)
 }
 
@@ -30,7 +30,15 @@ pub async fn get_image_description(
     request: web::Json<ImageDescRequest>,
     req: HttpRequest,
 ) -> HttpResponse {
-    let image = decode_base64(&request.data).expect("cannot decode image");
+    let image = match decode_base64(&request.data) {
+        Ok(image) => image,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ImageDescResponse {
+                data: String::from("invalid image"),
+                message: e.to_string(),
+            })
+        }
+    };
     let emebeddings = blip_model
         .run(&image)
         .expect("cannot decode stream");
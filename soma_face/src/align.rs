@@ -0,0 +1,148 @@
+//! five point face alignment for `ArcFace`,
+//! warps a face onto the standard 112x112 reference landmarks
+//! with a similarity transform (rotation + uniform scale + translation)
+
+use anyhow::{bail, Error, Result};
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// side length of the aligned face crop expected by `ArcFace`
+pub const ARCFACE_INPUT_SIZE: u32 = 112;
+
+/// reference landmarks for a 112x112 `ArcFace` crop, in order:
+/// left eye, right eye, nose tip, left mouth corner, right mouth corner
+pub const ARCFACE_REFERENCE_LANDMARKS: [[f32; 2]; 5] = [
+    [38.2946, 51.6963],
+    [73.5318, 51.5014],
+    [56.0252, 71.7366],
+    [41.5493, 92.3655],
+    [70.7299, 92.2041],
+];
+
+/// 2x3 affine matrix, maps `[x, y, 1]` to `[x', y']`
+pub type AffineMatrix = [[f32; 3]; 2];
+
+/// least squares similarity transform mapping `src` points onto `dst` points
+/// (closed form umeyama for 2d, reflections are not allowed)
+pub fn estimate_similarity_transform(
+    src: &[[f32; 2]],
+    dst: &[[f32; 2]],
+) -> Result<AffineMatrix, Error> {
+    if src.len() != dst.len() || src.len() < 2 {
+        bail!(
+            "need at least 2 matching point pairs, got {} and {}",
+            src.len(),
+            dst.len()
+        );
+    }
+    let n = src.len() as f32;
+    let (src_mx, src_my) = src
+        .iter()
+        .fold((0.0, 0.0), |(x, y), p| (x + p[0] / n, y + p[1] / n));
+    let (dst_mx, dst_my) = dst
+        .iter()
+        .fold((0.0, 0.0), |(x, y), p| (x + p[0] / n, y + p[1] / n));
+
+    let mut dot = 0.0;
+    let mut cross = 0.0;
+    let mut src_var = 0.0;
+    for (s, d) in src.iter().zip(dst.iter()) {
+        let (sx, sy) = (s[0] - src_mx, s[1] - src_my);
+        let (dx, dy) = (d[0] - dst_mx, d[1] - dst_my);
+        dot += sx * dx + sy * dy;
+        cross += sx * dy - sy * dx;
+        src_var += sx * sx + sy * sy;
+    }
+    if src_var <= f32::EPSILON {
+        bail!("source landmarks are degenerate (all points are the same)");
+    }
+    // a = s*cos(theta), b = s*sin(theta)
+    let a = dot / src_var;
+    let b = cross / src_var;
+    let tx = dst_mx - (a * src_mx - b * src_my);
+    let ty = dst_my - (b * src_mx + a * src_my);
+    Ok([[a, -b, tx], [b, a, ty]])
+}
+
+/// inverts a 2x3 affine matrix
+pub fn invert_affine(m: &AffineMatrix) -> Result<AffineMatrix, Error> {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    if det.abs() <= f32::EPSILON {
+        bail!("affine matrix is not invertible");
+    }
+    let inv_a = m[1][1] / det;
+    let inv_b = -m[0][1] / det;
+    let inv_c = -m[1][0] / det;
+    let inv_d = m[0][0] / det;
+    Ok([
+        [inv_a, inv_b, -(inv_a * m[0][2] + inv_b * m[1][2])],
+        [inv_c, inv_d, -(inv_c * m[0][2] + inv_d * m[1][2])],
+    ])
+}
+
+/// warps `image` with `transform` (source -> destination) into a `width` x `height`
+/// image, bilinear sampling, pixels outside of the source are black
+pub fn warp_affine(
+    image: &DynamicImage,
+    transform: &AffineMatrix,
+    width: u32,
+    height: u32,
+) -> Result<DynamicImage, Error> {
+    let inverse = invert_affine(transform)?;
+    let src = image.to_rgb8();
+    let (src_w, src_h) = src.dimensions();
+    let mut warped = RgbImage::new(width, height);
+    for (x, y, pixel) in warped.enumerate_pixels_mut() {
+        let (fx, fy) = (x as f32, y as f32);
+        let sx = inverse[0][0] * fx + inverse[0][1] * fy + inverse[0][2];
+        let sy = inverse[1][0] * fx + inverse[1][1] * fy + inverse[1][2];
+        *pixel = sample_bilinear(&src, src_w, src_h, sx, sy);
+    }
+    Ok(DynamicImage::ImageRgb8(warped))
+}
+
+fn sample_bilinear(src: &RgbImage, width: u32, height: u32, x: f32, y: f32) -> Rgb<u8> {
+    if x < 0.0 || y < 0.0 || x > (width - 1) as f32 || y > (height - 1) as f32 {
+        return Rgb([0, 0, 0]);
+    }
+    let x0 = x.floor() as u32;
+    let y0 = y.floor() as u32;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let wx = x - x0 as f32;
+    let wy = y - y0 as f32;
+    let p00 = src.get_pixel(x0, y0);
+    let p10 = src.get_pixel(x1, y0);
+    let p01 = src.get_pixel(x0, y1);
+    let p11 = src.get_pixel(x1, y1);
+    let mut out = [0u8; 3];
+    for c in 0..3 {
+        let top = p00[c] as f32 * (1.0 - wx) + p10[c] as f32 * wx;
+        let bottom = p01[c] as f32 * (1.0 - wx) + p11[c] as f32 * wx;
+        out[c] = (top * (1.0 - wy) + bottom * wy).round().clamp(0.0, 255.0) as u8;
+    }
+    Rgb(out)
+}
+
+/// reads the five `[x, y, ..]` keypoints from a [soma_core::common_utils::Bbox] `kpss`
+pub fn landmarks_from_kpss(kpss: &[Vec<f32>]) -> Result<[[f32; 2]; 5], Error> {
+    let points: Vec<[f32; 2]> = kpss
+        .iter()
+        .filter(|p| p.len() >= 2)
+        .map(|p| [p[0], p[1]])
+        .collect();
+    if points.len() != 5 {
+        bail!("expected 5 face keypoints, got {}", points.len());
+    }
+    Ok([points[0], points[1], points[2], points[3], points[4]])
+}
+
+/// aligns a face onto the `ArcFace` reference landmarks,
+/// `kpss` are the five keypoints of the face in `image` pixel coordinates
+pub fn align_face(image: &DynamicImage, kpss: &[Vec<f32>]) -> Result<DynamicImage, Error> {
+    if image.width() == 0 || image.height() == 0 {
+        bail!("cannot align an empty image");
+    }
+    let landmarks = landmarks_from_kpss(kpss)?;
+    let transform = estimate_similarity_transform(&landmarks, &ARCFACE_REFERENCE_LANDMARKS)?;
+    warp_affine(image, &transform, ARCFACE_INPUT_SIZE, ARCFACE_INPUT_SIZE)
+}
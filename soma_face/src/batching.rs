@@ -0,0 +1,187 @@
+//! dynamic batching for model inference.
+//!
+//! a [BatchQueue] owns a dedicated thread per model, concurrent requests are
+//! collected for up to `max_wait` (or until `max_batch_size` items are queued),
+//! run as a single batch off the async runtime and the results are sent back
+//! to each waiting request
+
+use crate::get_face::yolo::GetFaceYolo;
+use crate::get_face_vec::arcface::GetFaceVecArcFace;
+use anyhow::{anyhow, bail, Error, Result};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use soma_core::common_utils::Bbox;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// if false every request runs on its own
+    pub enabled: bool,
+    /// largest batch handed to the model
+    pub max_batch_size: usize,
+    /// how long the first queued request waits for others to join its batch
+    pub max_wait_ms: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            enabled: true,
+            max_batch_size: 8,
+            max_wait_ms: 5,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_millis(self.max_wait_ms)
+    }
+}
+
+struct BatchItem<I, O> {
+    input: I,
+    respond: oneshot::Sender<Result<O, Error>>,
+}
+
+/// queue in front of a model, cheap to share between workers through `web::Data`
+pub struct BatchQueue<I, O> {
+    name: String,
+    sender: mpsc::Sender<BatchItem<I, O>>,
+}
+
+impl<I: Send + 'static, O: Send + 'static> BatchQueue<I, O> {
+    /// spawns the batching thread, `run` gets every input of a batch and
+    /// must return exactly one output per input, in order
+    pub fn spawn<F>(name: &str, config: BatchConfig, run: F) -> Result<BatchQueue<I, O>, Error>
+    where
+        F: Fn(Vec<I>) -> Result<Vec<O>, Error> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel::<BatchItem<I, O>>();
+        let max_batch_size = config.max_batch_size.max(1);
+        let max_wait = config.max_wait();
+        std::thread::Builder::new()
+            .name(format!("{}-batch", name))
+            .spawn(move || {
+                // exits once every sender (every worker) is dropped
+                while let Ok(first) = receiver.recv() {
+                    let mut batch = vec![first];
+                    let deadline = Instant::now() + max_wait;
+                    while batch.len() < max_batch_size {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        match receiver.recv_timeout(remaining) {
+                            Ok(item) => batch.push(item),
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                    }
+                    run_batch(&run, batch);
+                }
+            })?;
+        Ok(BatchQueue {
+            name: name.to_string(),
+            sender,
+        })
+    }
+
+    /// queues one input and waits for its result,
+    /// blocks so call it from the blocking pool and not the event loop
+    pub fn submit(&self, input: I) -> Result<O, Error> {
+        let (respond, result) = oneshot::channel();
+        self.sender
+            .send(BatchItem { input, respond })
+            .map_err(|_| anyhow!("{} batch queue is closed", self.name))?;
+        result
+            .blocking_recv()
+            .map_err(|_| anyhow!("{} batch queue dropped the request", self.name))?
+    }
+
+    /// queues several inputs (they may end up in different batches)
+    /// and waits for all of them, results are in input order.
+    /// blocks like [BatchQueue::submit]
+    pub fn submit_many(&self, inputs: Vec<I>) -> Result<Vec<O>, Error> {
+        let mut pending = vec![];
+        for input in inputs {
+            let (respond, result) = oneshot::channel();
+            self.sender
+                .send(BatchItem { input, respond })
+                .map_err(|_| anyhow!("{} batch queue is closed", self.name))?;
+            pending.push(result);
+        }
+        let mut outputs = vec![];
+        for result in pending {
+            outputs.push(
+                result
+                    .blocking_recv()
+                    .map_err(|_| anyhow!("{} batch queue dropped the request", self.name))??,
+            );
+        }
+        Ok(outputs)
+    }
+}
+
+/// runs one batch and fans the results back out,
+/// a failed batch fails every request in it
+fn run_batch<I, O, F>(run: &F, batch: Vec<BatchItem<I, O>>)
+where
+    F: Fn(Vec<I>) -> Result<Vec<O>, Error>,
+{
+    let (inputs, responders): (Vec<I>, Vec<_>) =
+        batch.into_iter().map(|x| (x.input, x.respond)).unzip();
+    let expected = inputs.len();
+    let outputs = run(inputs).and_then(|outputs| match outputs.len() == expected {
+        true => Ok(outputs),
+        false => bail!("batch returned {} outputs for {} inputs", outputs.len(), expected),
+    });
+    match outputs {
+        Ok(outputs) => {
+            for (respond, output) in responders.into_iter().zip(outputs) {
+                // the request may have been cancelled, nothing to do then
+                let _ = respond.send(Ok(output));
+            }
+        }
+        Err(e) => {
+            let message = format!("{:#}", e);
+            for respond in responders {
+                let _ = respond.send(Err(anyhow!(message.clone())));
+            }
+        }
+    }
+}
+
+/// one image to run yolo on with its own thresholds
+pub struct DetectionJob {
+    pub image: DynamicImage,
+    pub confidence: f32,
+    pub iou: f32,
+}
+
+pub type DetectionQueue = BatchQueue<DetectionJob, Vec<Bbox>>;
+
+pub type EmbeddingQueue = BatchQueue<DynamicImage, Vec<f32>>;
+
+/// batches yolo detections, see [GetFaceYolo::detect_batch]
+pub fn detection_queue(
+    model: Arc<GetFaceYolo>,
+    config: BatchConfig,
+) -> Result<DetectionQueue, Error> {
+    BatchQueue::spawn("yolo", config, move |jobs: Vec<DetectionJob>| {
+        let thresholds: Vec<(f32, f32)> = jobs.iter().map(|x| (x.confidence, x.iou)).collect();
+        let images: Vec<DynamicImage> = jobs.into_iter().map(|x| x.image).collect();
+        model.detect_batch(&images, &thresholds)
+    })
+}
+
+/// batches arcface embeddings, see [GetFaceVecArcFace::forward_batch]
+pub fn embedding_queue(
+    model: Arc<GetFaceVecArcFace>,
+    config: BatchConfig,
+) -> Result<EmbeddingQueue, Error> {
+    BatchQueue::spawn("arcface", config, move |faces: Vec<DynamicImage>| {
+        model.forward_batch(&faces)
+    })
+}
@@ -0,0 +1,285 @@
+//! `soma_face` server configuration.
+//!
+//! read from a toml or yaml file (picked by extension), then overridden by
+//! `SOMA_FACE_*` environment variables and finally by cli args.
+//! every field has a default so an empty (or missing) file gives the old
+//! hardcoded setup: yolo detection + arcface vectors on 640x640 / 112x112
+
+use crate::batching::BatchConfig;
+use crate::quality::QualityConfig;
+use crate::webserver::blocking::BlockingConfig;
+use anyhow::{bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use soma_core::image_ingest::IngestConfig;
+use soma_core::onnx_backend::{BackendConfig, BackendKind};
+use std::env;
+use std::path::Path;
+
+/// file read when `--config` / `SOMA_FACE_CONFIG` are not set (if it exists)
+pub const DEFAULT_CONFIG_PATH: &str = "./soma_face.toml";
+
+pub const DEFAULT_YOLO_PATH: &str = "./models/yoloface_8n.onnx";
+pub const DEFAULT_RETINAFACE_PATH: &str = "./models/det_10g.onnx";
+pub const DEFAULT_ARCFACE_PATH: &str = "./models/arcfaceresnet100-8.onnx";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaceApiConfig {
+    /// number of actix workers
+    pub workers: usize,
+    pub models: ModelsConfig,
+    pub routes: RoutesConfig,
+    /// dynamic batching of concurrent yolo / arcface requests
+    pub batching: BatchConfig,
+    /// bounded pool running image decoding and inference off the event loop
+    pub blocking: BlockingConfig,
+    /// image size limit and the hosts url images may be fetched from
+    pub ingest: IngestConfig,
+    /// pass / fail thresholds of `/face_quality` and `quality=true` detections
+    pub quality: QualityConfig,
+    /// match threshold of `/verify`
+    pub verify: VerifyConfig,
+}
+
+/// which models are loaded, a missing section means the model is not loaded.
+/// `retinaface` together with `yolo` loads the combined [crate::get_face::FaceExtractor]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelsConfig {
+    pub yolo: DetectorConfig,
+    pub retinaface: Option<DetectorConfig>,
+    pub arcface: Option<EmbedderConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectorConfig {
+    pub path: String,
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// intra-op threads, unset lets the backend decide
+    #[serde(default)]
+    pub threads: Option<usize>,
+    #[serde(default = "default_detector_size")]
+    pub width: i32,
+    #[serde(default = "default_detector_size")]
+    pub height: i32,
+    /// IoU above which overlapping detections are suppressed,
+    /// unset keeps the model default
+    #[serde(default)]
+    pub nms_threshold: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbedderConfig {
+    pub path: String,
+    #[serde(default)]
+    pub backend: BackendKind,
+    /// intra-op threads, unset lets the backend decide
+    #[serde(default)]
+    pub threads: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerifyConfig {
+    /// cosine similarity at or above which two faces are the same person.
+    /// the default suits the arcface resnet100 export, recalibrate it on
+    /// pairs from your own cameras when changing the model
+    pub threshold: f32,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        VerifyConfig { threshold: 0.35 }
+    }
+}
+
+/// per route enablement, routes whose models are not loaded are skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutesConfig {
+    /// `/get_face`
+    pub detect: bool,
+    /// `/get_largest_face`
+    pub largest_face: bool,
+    /// `/face_quality`
+    pub quality: bool,
+    /// `/get_vec`, `/get_vecs` and `/verify`
+    pub vectors: bool,
+    /// swagger ui under `/docs`
+    pub docs: bool,
+}
+
+fn default_detector_size() -> i32 {
+    640
+}
+
+impl DetectorConfig {
+    pub fn new(path: &str) -> DetectorConfig {
+        DetectorConfig {
+            path: path.to_string(),
+            backend: BackendKind::default(),
+            threads: None,
+            width: default_detector_size(),
+            height: default_detector_size(),
+            nms_threshold: None,
+        }
+    }
+
+    pub fn backend_config(&self) -> BackendConfig {
+        BackendConfig::new(self.backend).with_intra_threads(self.threads)
+    }
+}
+
+impl EmbedderConfig {
+    pub fn new(path: &str) -> EmbedderConfig {
+        EmbedderConfig {
+            path: path.to_string(),
+            backend: BackendKind::default(),
+            threads: None,
+        }
+    }
+
+    pub fn backend_config(&self) -> BackendConfig {
+        BackendConfig::new(self.backend).with_intra_threads(self.threads)
+    }
+}
+
+impl Default for FaceApiConfig {
+    fn default() -> Self {
+        FaceApiConfig {
+            workers: 4,
+            models: ModelsConfig::default(),
+            routes: RoutesConfig::default(),
+            batching: BatchConfig::default(),
+            blocking: BlockingConfig::default(),
+            ingest: IngestConfig::default(),
+            quality: QualityConfig::default(),
+            verify: VerifyConfig::default(),
+        }
+    }
+}
+
+impl Default for ModelsConfig {
+    fn default() -> Self {
+        ModelsConfig {
+            yolo: DetectorConfig::new(DEFAULT_YOLO_PATH),
+            retinaface: None,
+            arcface: Some(EmbedderConfig::new(DEFAULT_ARCFACE_PATH)),
+        }
+    }
+}
+
+impl Default for RoutesConfig {
+    fn default() -> Self {
+        RoutesConfig {
+            detect: true,
+            largest_face: true,
+            quality: true,
+            vectors: true,
+            docs: true,
+        }
+    }
+}
+
+impl FaceApiConfig {
+    /// parses a toml (`.toml`) or yaml (`.yaml` / `.yml`) config file
+    pub fn from_file(path: &str) -> Result<FaceApiConfig, Error> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("cannot read config file `{}`", path))?;
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let config = match extension.as_str() {
+            "toml" => toml::from_str(&contents)
+                .with_context(|| format!("invalid toml in `{}`", path))?,
+            "yaml" | "yml" => serde_yaml::from_str(&contents)
+                .with_context(|| format!("invalid yaml in `{}`", path))?,
+            _ => bail!("config file `{}` must be `.toml`, `.yaml` or `.yml`", path),
+        };
+        Ok(config)
+    }
+
+    /// loads `path` if given, otherwise `SOMA_FACE_CONFIG`, otherwise
+    /// [DEFAULT_CONFIG_PATH] if it exists, otherwise the defaults.
+    /// environment overrides are applied on top
+    pub fn load(path: Option<&str>) -> Result<FaceApiConfig, Error> {
+        let path = path
+            .map(|x| x.to_string())
+            .or_else(|| env::var("SOMA_FACE_CONFIG").ok());
+        let mut config = match path {
+            Some(path) => FaceApiConfig::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).is_file() => {
+                FaceApiConfig::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => FaceApiConfig::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// `SOMA_FACE_WORKERS`, `SOMA_FACE_YOLO_PATH`, `SOMA_FACE_RETINAFACE_PATH`,
+    /// `SOMA_FACE_ARCFACE_PATH` and `SOMA_FACE_URL_ALLOWLIST` (comma separated hosts),
+    /// setting a model path also enables the model
+    pub fn apply_env(&mut self) -> Result<(), Error> {
+        if let Ok(workers) = env::var("SOMA_FACE_WORKERS") {
+            self.workers = workers
+                .parse()
+                .with_context(|| format!("invalid SOMA_FACE_WORKERS `{}`", workers))?;
+        }
+        if let Ok(path) = env::var("SOMA_FACE_YOLO_PATH") {
+            self.models.yolo.path = path;
+        }
+        if let Ok(path) = env::var("SOMA_FACE_RETINAFACE_PATH") {
+            self.enable_retinaface(true);
+            if let Some(retinaface) = self.models.retinaface.as_mut() {
+                retinaface.path = path;
+            }
+        }
+        if let Ok(path) = env::var("SOMA_FACE_ARCFACE_PATH") {
+            self.enable_arcface(true);
+            if let Some(arcface) = self.models.arcface.as_mut() {
+                arcface.path = path;
+            }
+        }
+        if let Ok(hosts) = env::var("SOMA_FACE_URL_ALLOWLIST") {
+            self.ingest.url_allowlist = hosts
+                .split(',')
+                .map(|x| x.trim().to_string())
+                .filter(|x| !x.is_empty())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// loads retinaface (with the default path if it is not configured) or drops it
+    pub fn enable_retinaface(&mut self, enabled: bool) {
+        match enabled {
+            true => {
+                if self.models.retinaface.is_none() {
+                    self.models.retinaface = Some(DetectorConfig::new(DEFAULT_RETINAFACE_PATH));
+                }
+            }
+            false => self.models.retinaface = None,
+        }
+    }
+
+    /// loads arcface (with the default path if it is not configured) or drops it
+    pub fn enable_arcface(&mut self, enabled: bool) {
+        match enabled {
+            true => {
+                if self.models.arcface.is_none() {
+                    self.models.arcface = Some(EmbedderConfig::new(DEFAULT_ARCFACE_PATH));
+                }
+            }
+            false => self.models.arcface = None,
+        }
+    }
+
+    /// true if the vector routes are enabled and arcface is loaded
+    pub fn serves_vectors(&self) -> bool {
+        self.routes.vectors && self.models.arcface.is_some()
+    }
+}
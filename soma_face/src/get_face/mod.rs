@@ -3,15 +3,16 @@ pub mod retinaface;
 pub mod yolo;
 
 // use crate::core::{
-//     common_utils::{sort_conf_bbox, Bbox},
+//     common_utils::{non_maximum_suppression, sort_conf_bbox, Bbox},
 //     onnx_backend::{Inference, InferenceResult},
 // };
 use soma_core::{
-    common_utils::{sort_conf_bbox, Bbox},
-    onnx_backend::{Inference, InferenceResult}
+    common_utils::{non_maximum_suppression, sort_conf_bbox, Bbox},
+    onnx_backend::{BackendConfig, Inference, InferenceResult, ModelLoadError}
 };
 use anyhow::{Error, Result};
 use image::DynamicImage;
+use std::sync::Arc;
 use retinaface::GetFaceRetinaface;
 use yolo::GetFaceYolo;
 
@@ -20,7 +21,8 @@ pub struct FaceExtractor {
     pub width: i32,
     pub height: i32,
     pub retina_model: GetFaceRetinaface,
-    pub yolo_model: GetFaceYolo,
+    /// shared with the yolo batch queue
+    pub yolo_model: Arc<GetFaceYolo>,
 }
 
 impl FaceExtractor {
@@ -30,14 +32,37 @@ impl FaceExtractor {
         yolo_path: &str,
         width: i32,
         height: i32,
-    ) -> Result<FaceExtractor, Error> {
-        let load_retina_model = GetFaceRetinaface::new(retina_path, width, height, false).unwrap();
-        let load_yolo_model = GetFaceYolo::new(yolo_path, width, height, false).unwrap();
+    ) -> Result<FaceExtractor, ModelLoadError> {
+        FaceExtractor::new_with_backend(retina_path, yolo_path, BackendConfig::default(), width, height)
+    }
+
+    /// wraps already loaded detectors, `width` and `height` are taken from yolo
+    pub fn from_models(retina_model: GetFaceRetinaface, yolo_model: GetFaceYolo) -> FaceExtractor {
+        FaceExtractor {
+            width: yolo_model.width,
+            height: yolo_model.height,
+            retina_model,
+            yolo_model: Arc::new(yolo_model),
+        }
+    }
+
+    /// both detectors are loaded on `backend`
+    pub fn new_with_backend(
+        retina_path: &str,
+        yolo_path: &str,
+        backend: BackendConfig,
+        width: i32,
+        height: i32,
+    ) -> Result<FaceExtractor, ModelLoadError> {
+        let load_retina_model =
+            GetFaceRetinaface::new_with_backend(retina_path, backend, width, height, false)?;
+        let load_yolo_model =
+            GetFaceYolo::new_with_backend(yolo_path, backend, width, height, false)?;
         Ok(FaceExtractor {
             width,
             height,
             retina_model: load_retina_model,
-            yolo_model: load_yolo_model,
+            yolo_model: Arc::new(load_yolo_model),
         })
     }
     fn forward_retinaface(
@@ -66,6 +91,19 @@ impl FaceExtractor {
         }
     }
 
+    /// every face found by either model, overlapping detections
+    /// from both models are merged with NMS (highest confidence wins)
+    pub fn extract_faces_from_image(
+        &self,
+        input_image: &DynamicImage,
+        confidence_threshold: f32,
+        iou_threshold: f32,
+    ) -> Result<Vec<Bbox>, Error> {
+        let mut combined_res = self.forward_yolo(input_image, confidence_threshold)?;
+        combined_res.extend(self.forward_retinaface(input_image, confidence_threshold)?);
+        Ok(non_maximum_suppression(combined_res, iou_threshold))
+    }
+
     pub fn extract_face_from_image(
         &self,
         input_image: &DynamicImage,
@@ -1,36 +1,108 @@
-use soma_core::common_utils::{image_to_ndarray, Bbox};
-use soma_core::onnx_backend::{Inference, InferenceResult, OnnxModel};
-use anyhow::{Error, Result};
+use soma_core::common_utils::{
+    image_to_ndarray, letterbox_with, non_maximum_suppression, Bbox, Detector, Letterbox,
+    LetterboxPadding,
+};
+use soma_core::onnx_backend::{BackendConfig, Inference, InferenceResult, ModelLoadError, OnnxModel};
+use anyhow::{bail, Error, Result};
 use image::imageops;
 use image::{DynamicImage, RgbImage};
 use ndarray::{
     array, s, stack, Array, Array1, Array2, Array3, ArrayBase, ArrayD, ArrayView2, ArrayView4,
     ArrayViewD, Axis, Dim, Ix2, Ix3, IxDyn, IxDynImpl, OwnedRepr,
 };
-use ort::{
-    inputs, CPUExecutionProvider, ExecutionProvider, GraphOptimizationLevel, Session,
-    SessionOutputs,
-};
+
+/// default IoU threshold for SCRFD non maximum suppression
+pub const SCRFD_NMS_THRESHOLD: f32 = 0.4;
 
 pub struct GetFaceRetinaface {
     pub onnx_model: OnnxModel,
     pub width: i32,
     pub height: i32,
+    /// IoU above which overlapping detections are suppressed
+    pub nms_threshold: f32,
+    /// output layout, read once at load time
+    pub config: ScrfdFaceConfig,
 }
 
 impl GetFaceRetinaface {
+    /// `width` and `height` are the network input size,
+    /// both must be multiples of the largest stride (32, or 128 for 5 stride models)
     pub fn new(
         model_path: &str,
         width: i32,
         height: i32,
         use_fp16: bool,
-    ) -> Result<GetFaceRetinaface, Error> {
+    ) -> Result<GetFaceRetinaface, ModelLoadError> {
+        GetFaceRetinaface::new_with_backend(
+            model_path,
+            BackendConfig::default(),
+            width,
+            height,
+            use_fp16,
+        )
+    }
+
+    pub fn new_with_backend(
+        model_path: &str,
+        backend: BackendConfig,
+        width: i32,
+        height: i32,
+        use_fp16: bool,
+    ) -> Result<GetFaceRetinaface, ModelLoadError> {
+        let onnx_model = OnnxModel::load_with_backend(
+            backend,
+            model_path,
+            &[1, 3, height as usize, width as usize],
+            use_fp16,
+        )?;
+        let config = GetFaceRetinaface::validate(&onnx_model, width, height)?;
         Ok(GetFaceRetinaface {
-            onnx_model: GetFaceRetinaface::load(model_path, use_fp16),
+            onnx_model,
             width: width,
             height: height,
+            nms_threshold: SCRFD_NMS_THRESHOLD,
+            config,
         })
     }
+
+    /// single `[1, 3, h, w]` input and one of the suppourted SCRFD output layouts
+    pub fn validate(
+        model: &OnnxModel,
+        width: i32,
+        height: i32,
+    ) -> Result<ScrfdFaceConfig, ModelLoadError> {
+        model.expect_input_shape("scrfd", &[1, 3, height as i64, width as i64])?;
+        ScrfdFaceConfig::from_loaded_session(model)
+    }
+
+    pub fn with_nms_threshold(mut self, nms_threshold: f32) -> GetFaceRetinaface {
+        self.nms_threshold = nms_threshold;
+        self
+    }
+
+    /// [Inference::forward] with a per call NMS IoU threshold
+    pub fn detect(
+        &self,
+        input_image: &DynamicImage,
+        confidence_threshold: f32,
+        iou_threshold: f32,
+    ) -> Result<Vec<Bbox>, Error> {
+        let (preproces_image, letterbox) =
+            preprocess_face_f32_retina(input_image, self.width as u32, self.height as u32)?;
+        let outputs = self.onnx_model.run_f32(preproces_image.into_dyn().view())?;
+        let process_infernece = process_detections(
+            &outputs,
+            &self.config,
+            self.width as usize,
+            self.height as usize,
+            confidence_threshold,
+            iou_threshold,
+        )?;
+        Ok(process_infernece
+            .iter()
+            .map(|x| x.apply_letterbox(&letterbox))
+            .collect())
+    }
 }
 
 impl Inference for GetFaceRetinaface {
@@ -39,80 +111,83 @@ impl Inference for GetFaceRetinaface {
         input_image: &DynamicImage,
         confidence_threshold: f32,
     ) -> Result<InferenceResult, Error> {
-        let model_config = ScrfdFaceConfig::from_loaded_session(&self.onnx_model);
-        let preproces_image = preprocess_face_f32_retina(input_image)?;
-        let inference = self
-            .onnx_model
-            .model
-            .run(inputs!["input.1" => preproces_image.view()]?)?;
-        let mut process_infernece = process_detections(
-            inference,
-            model_config.stride_fpn,
-            model_config.fmc,
-            model_config.anchors,
+        Ok(InferenceResult::FaceDetection(self.detect(
+            input_image,
             confidence_threshold,
-            true,
-        )?;
-        Ok(InferenceResult::FaceDetection(
-            process_infernece
-                .iter_mut()
-                .map(|x| x.apply_image_scale(input_image, self.width as f32, self.height as f32))
-                .collect(),
-        ))
+            self.nms_threshold,
+        )?))
     }
-    fn load(model_path: &str, fp16: bool) -> OnnxModel {
-        let model: ort::Session = Session::builder()
-            .unwrap()
-            .with_optimization_level(GraphOptimizationLevel::Level3)
-            .unwrap()
-            .with_execution_providers([CPUExecutionProvider::default().build()])
-            .unwrap()
-            .commit_from_file(model_path)
-            .unwrap();
-        OnnxModel {
-            model: model,
-            is_fp16: fp16,
-        }
+    /// loads with the default 640x640 input
+    fn load(model_path: &str, fp16: bool) -> Result<OnnxModel, ModelLoadError> {
+        let model = OnnxModel::load(model_path, &[1, 3, 640, 640], fp16)?;
+        GetFaceRetinaface::validate(&model, 640, 640)?;
+        Ok(model)
     }
 }
 
-struct ScrfdFaceConfig {
-    fmc: usize,
-    stride_fpn: Vec<i32>,
-    anchors: usize,
+/// output layout of a SCRFD model,
+/// outputs are ordered `[scores * fmc, bboxes * fmc, (kps * fmc)]`
+#[derive(Debug, Clone)]
+pub struct ScrfdFaceConfig {
+    pub fmc: usize,
+    pub stride_fpn: Vec<i32>,
+    pub anchors: usize,
+    pub use_kps: bool,
 }
 
-/// configs for 10g / 2.5g kpss , other models are not suppourted yet
+/// configs for the insightface SCRFD exports (3 or 5 strides, with or without kpss),
+/// other models are not suppourted yet
 impl ScrfdFaceConfig {
-    pub fn from_loaded_session(model: &OnnxModel) -> ScrfdFaceConfig {
-        match model.model.outputs.len() {
-            6 => ScrfdFaceConfig {
-                fmc: 3,
-                stride_fpn: vec![8, 16, 32],
-                anchors: 2,
-            },
-            9 => ScrfdFaceConfig {
+    pub fn from_output_count(output_count: usize) -> Option<ScrfdFaceConfig> {
+        match output_count {
+            6 | 9 => Some(ScrfdFaceConfig {
                 fmc: 3,
                 stride_fpn: vec![8, 16, 32],
                 anchors: 2,
-            },
-            _ => {
-                panic!("SCRFD model not suppourted (yet)")
-            }
+                use_kps: output_count == 9,
+            }),
+            10 | 15 => Some(ScrfdFaceConfig {
+                fmc: 5,
+                stride_fpn: vec![8, 16, 32, 64, 128],
+                anchors: 1,
+                use_kps: output_count == 15,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_loaded_session(model: &OnnxModel) -> Result<ScrfdFaceConfig, ModelLoadError> {
+        match ScrfdFaceConfig::from_output_count(model.output_count()) {
+            Some(config) => Ok(config),
+            None => Err(ModelLoadError::OutputMismatch {
+                model: "scrfd".to_string(),
+                expected: "6, 9, 10 or 15 outputs (insightface SCRFD exports)".to_string(),
+                found: format!("{} output(s)", model.output_count()),
+            }),
         }
     }
 }
 
+/// letterboxes the image into the `input_width` x `input_height` model input,
+/// returns the NCHW tensor and the [Letterbox] to map detections back.
+/// SCRFD is fed the resized image at the top left on black, like the insightface reference
 pub fn preprocess_face_f32_retina(
     image_source: &DynamicImage,
-) -> Result<ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>, Error> {
-    let img = image_source.resize_exact(640, 640, imageops::FilterType::Triangle);
+    input_width: u32,
+    input_height: u32,
+) -> Result<(ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>, Letterbox), Error> {
+    let (img, letterbox) = letterbox_with(
+        image_source,
+        input_width,
+        input_height,
+        LetterboxPadding::TopLeft,
+    );
 
     let mut ndarray_image = image_to_ndarray(&img);
     ndarray_image -= 127.5;
     ndarray_image *= 1.0 / 128.0;
     let _final = ndarray_image.permuted_axes((2, 0, 1));
-    Ok(_final.insert_axis(ndarray::Axis(0)))
+    Ok((_final.insert_axis(ndarray::Axis(0)), letterbox))
 }
 
 fn stack_anchor_center(anchor_centers: &Array2<f32>, num_anchors: usize) -> Array2<f32> {
@@ -182,76 +257,278 @@ fn distance2bbox(
     stack(Axis(1), &[x1.view(), y1.view(), x2.view(), y2.view()]).unwrap()
 }
 
+/// `[N, 10]` -> `[N, 5, 2]`, the decoded kpss come out of [stack] column major
+/// and `into_shape` would reshape them in that order, mixing up the points
 fn reshape_kpss(kpss: Array2<f32>) -> Array3<f32> {
     let shape = kpss.shape();
     let new_shape = (shape[0], shape[1] / 2, 2);
-    kpss.into_shape(new_shape).expect("Failed to reshape array")
+    kpss.as_standard_layout()
+        .into_owned()
+        .into_shape(new_shape)
+        .expect("Failed to reshape array")
+}
+
+/// flattens a `[N, C]` or batched `[1, N, C]` output into `[N, C]`
+fn output_rows(output: &ArrayD<f32>, cols: usize) -> Result<Array2<f32>, Error> {
+    let rows = output.len() / cols;
+    if rows * cols != output.len() {
+        bail!(
+            "SCRFD output of shape {:?} cannot be split into rows of {}",
+            output.shape(),
+            cols
+        );
+    }
+    Ok(output
+        .as_standard_layout()
+        .into_owned()
+        .into_shape((rows, cols))?)
 }
 
-/// get bboxes from onnx outputs
+/// get bboxes from SCRFD outputs (in input pixel coordinates),
+/// detections from every stride are merged then suppressed with NMS
+///
+/// `outputs` must be in model output order, see [ScrfdFaceConfig]
 pub fn process_detections(
-    inference: SessionOutputs,
-    stride_fpn: Vec<i32>,
-    fmc: usize,
-    num_anchors: usize,
+    outputs: &[ArrayD<f32>],
+    config: &ScrfdFaceConfig,
+    input_width: usize,
+    input_height: usize,
     confidence_thresh: f32,
-    include_kps: bool, // returns face keypoints
+    iou_thresh: f32,
 ) -> Result<Vec<Bbox>, Error> {
+    let expected_outputs = config.fmc * if config.use_kps { 3 } else { 2 };
+    if outputs.len() < expected_outputs {
+        bail!(
+            "SCRFD model returned {} outputs, expected {}",
+            outputs.len(),
+            expected_outputs
+        );
+    }
     let mut raw_bboxes: Vec<Bbox> = vec![];
-    // process
-    for (idx, stride) in stride_fpn.iter().enumerate() {
-        let scores = &inference[idx].try_extract_tensor::<f32>()?.into_owned();
-        let _bbox_preds = &inference[idx + fmc]
-            .try_extract_tensor::<f32>()?
-            .into_owned();
-
-        let bbox_preds = _bbox_preds.to_owned() * (stride.to_owned() as f32);
-        let height = (640 / stride) as usize; // 640 = input size.
-        let width = (640 / stride) as usize;
+    for (idx, stride) in config.stride_fpn.iter().enumerate() {
+        let stride_f = *stride as f32;
+        let height = input_height / *stride as usize;
+        let width = input_width / *stride as usize;
+        let anchor_count = height * width * config.anchors;
+
+        let scores = output_rows(&outputs[idx], 1)?;
+        let bbox_preds = output_rows(&outputs[idx + config.fmc], 4)? * stride_f;
+        if scores.nrows() != anchor_count || bbox_preds.nrows() != anchor_count {
+            bail!(
+                "stride {} expects {} anchors for a {}x{} input, model returned {}",
+                stride,
+                anchor_count,
+                input_width,
+                input_height,
+                scores.nrows()
+            );
+        }
+
         let ac = anchor_centers(height, width);
+        let _anchor_centers = (ac * stride_f).into_shape((height * width, 2))?;
+        let _stack = stack_anchor_center(&_anchor_centers, config.anchors);
+        let scaled_bbox = distance2bbox(&_stack.view(), &bbox_preds.into_dyn());
+        let _reshape_kpss = match config.use_kps {
+            true => {
+                let kps = output_rows(&outputs[idx + config.fmc * 2], 10)? * stride_f;
+                let decoded_kps = distance2kps(&_stack.view(), &kps.into_dyn().view(), None);
+                Some(reshape_kpss(decoded_kps))
+            }
+            false => None,
+        };
 
-        let mut _anchor_centers = (ac.to_owned() * (stride.to_owned() as f32))
-            .into_shape((height * width, 2))
-            .unwrap();
-        let _stack = stack_anchor_center(&_anchor_centers, num_anchors as usize);
-        let scaled_bbox = distance2bbox(&_stack.view(), &bbox_preds);
-        let mut _reshape_kpss: ArrayBase<OwnedRepr<f32>, Dim<[usize; 3]>> = array![[[]]];
-        if include_kps == true {
-            let mut _kps = &inference[idx + fmc * 2]
-                .try_extract_tensor::<f32>()?
-                .into_owned();
-            let kps = _kps * stride.to_owned() as f32;
-            let decoded_kps = distance2kps(&_stack.view(), &kps.view(), None);
-            _reshape_kpss = reshape_kpss(decoded_kps);
+        raw_bboxes.extend(scores.column(0).indexed_iter().filter_map(|(row, conf)| {
+            if *conf < confidence_thresh {
+                return None;
+            }
+            let kpss = match &_reshape_kpss {
+                Some(kpss) => kpss
+                    .slice(s![row, .., ..])
+                    .outer_iter()
+                    .map(|x| x.to_vec())
+                    .collect(),
+                None => vec![vec![]],
+            };
+            Some(Bbox {
+                x1: scaled_bbox[[row, 0]],
+                y1: scaled_bbox[[row, 1]],
+                x2: scaled_bbox[[row, 2]],
+                y2: scaled_bbox[[row, 3]],
+                confidence: *conf,
+                kpss,
+                detector: Detector::Retinaface,
+            })
+        }));
+    }
+    Ok(non_maximum_suppression(raw_bboxes, iou_thresh))
+}
+
+#[cfg(test)]
+mod tests {
+    //! saved SCRFD output tensors, see `tests/fixtures/scrfd/make_fixtures.py`.
+    //! every fixture has the same faces, placed on known anchors:
+    //! a strong face on stride 8, the same face again on stride 16 (IoU 0.6 with it),
+    //! another face on a coarser stride and one face under the confidence threshold
+
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Tensor {
+        shape: Vec<usize>,
+        data: Vec<f32>,
+    }
+
+    #[derive(Deserialize)]
+    struct Fixture {
+        input_width: usize,
+        input_height: usize,
+        outputs: Vec<Tensor>,
+    }
+
+    impl Fixture {
+        fn load(json: &str) -> Fixture {
+            serde_json::from_str(json).unwrap()
+        }
+
+        fn outputs(&self) -> Vec<ArrayD<f32>> {
+            self.outputs
+                .iter()
+                .map(|x| ArrayD::from_shape_vec(IxDyn(&x.shape), x.data.clone()).unwrap())
+                .collect()
         }
 
-        let _fucc: Vec<Bbox> = scores
-            .indexed_iter()
-            .enumerate()
-            .filter_map(|(idx, (val, conf))| {
-                if conf >= &confidence_thresh {
-                    Some(Bbox {
-                        x1: scaled_bbox.slice(s![val[0], ..]).to_owned()[0],
-                        y1: scaled_bbox.slice(s![val[0], ..]).to_owned()[1],
-                        x2: scaled_bbox.slice(s![val[0], ..]).to_owned()[2],
-                        y2: scaled_bbox.slice(s![val[0], ..]).to_owned()[3],
-                        confidence: conf.to_owned(),
-                        kpss: if include_kps {
-                            _reshape_kpss
-                                .slice(s![val[0], .., ..])
-                                .outer_iter()
-                                .map(|x| x.to_vec())
-                                .collect()
-                        } else {
-                            vec![vec![]]
-                        },
-                    })
-                } else {
-                    None
+        fn detect(&self, iou: f32) -> Vec<Bbox> {
+            let config = ScrfdFaceConfig::from_output_count(self.outputs.len()).unwrap();
+            process_detections(
+                &self.outputs(),
+                &config,
+                self.input_width,
+                self.input_height,
+                0.5,
+                iou,
+            )
+            .unwrap()
+        }
+    }
+
+    /// x1, y1, x2, y2, confidence, keypoints
+    type Expected = ([f32; 4], f32, Vec<[f32; 2]>);
+
+    const STRONG: ([f32; 4], f32) = ([48.0, 48.0, 112.0, 112.0], 0.9);
+    const SHIFTED: ([f32; 4], f32) = ([64.0, 48.0, 128.0, 112.0], 0.8);
+    const STRONG_KPS: [[f32; 2]; 5] = [[68.0, 72.0], [92.0, 72.0], [80.0, 84.0], [72.0, 96.0], [88.0, 96.0]];
+    /// no keypoint offsets, every point sits on the anchor center
+    const SHIFTED_KPS: [[f32; 2]; 5] = [[96.0, 80.0]; 5];
+
+    fn expect(bbox: ([f32; 4], f32), kpss: &[[f32; 2]]) -> Expected {
+        (bbox.0, bbox.1, kpss.to_vec())
+    }
+
+    fn assert_detections(found: &[Bbox], expected: &[Expected]) {
+        assert_eq!(found.len(), expected.len(), "{:?}", found);
+        for (bbox, (coords, confidence, kpss)) in found.iter().zip(expected) {
+            let found_coords = [bbox.x1, bbox.y1, bbox.x2, bbox.y2];
+            for (a, b) in found_coords.iter().zip(coords) {
+                assert!((a - b).abs() < 1e-3, "{:?} != {:?}", found_coords, coords);
+            }
+            assert!((bbox.confidence - confidence).abs() < 1e-6);
+            assert_eq!(bbox.detector, Detector::Retinaface);
+            match kpss.is_empty() {
+                // models without keypoints keep the single empty entry
+                true => assert_eq!(bbox.kpss, vec![Vec::<f32>::new()]),
+                false => {
+                    assert_eq!(bbox.kpss.len(), kpss.len());
+                    for (a, b) in bbox.kpss.iter().zip(kpss) {
+                        assert!((a[0] - b[0]).abs() < 1e-3 && (a[1] - b[1]).abs() < 1e-3, "{:?} != {:?}", a, b);
+                    }
                 }
-            })
-            .collect();
-        raw_bboxes = _fucc;
+            }
+        }
+    }
+
+    #[test]
+    fn config_from_output_count() {
+        for (count, fmc, anchors, use_kps) in [(6, 3, 2, false), (9, 3, 2, true), (10, 5, 1, false), (15, 5, 1, true)] {
+            let config = ScrfdFaceConfig::from_output_count(count).unwrap();
+            assert_eq!((config.fmc, config.anchors, config.use_kps), (fmc, anchors, use_kps));
+            assert_eq!(config.stride_fpn.len(), fmc);
+        }
+        assert!(ScrfdFaceConfig::from_output_count(3).is_none());
+        assert!(ScrfdFaceConfig::from_output_count(12).is_none());
+    }
+
+    #[test]
+    fn scrfd_6_outputs_640() {
+        let fixture = Fixture::load(include_str!("../../tests/fixtures/scrfd/scrfd_6_640x640.json"));
+        let other = ([416.0, 416.0, 544.0, 544.0], 0.7);
+        assert_detections(&fixture.detect(0.4), &[expect(STRONG, &[]), expect(other, &[])]);
+        assert_detections(
+            &fixture.detect(0.7),
+            &[expect(STRONG, &[]), expect(SHIFTED, &[]), expect(other, &[])],
+        );
+    }
+
+    #[test]
+    fn scrfd_9_outputs_320x256_batched() {
+        let fixture = Fixture::load(include_str!("../../tests/fixtures/scrfd/scrfd_9_320x256.json"));
+        let other = expect(
+            ([160.0, 96.0, 288.0, 224.0], 0.7),
+            &[[176.0, 128.0], [272.0, 128.0], [224.0, 176.0], [192.0, 224.0], [256.0, 224.0]],
+        );
+        assert_detections(
+            &fixture.detect(0.4),
+            &[expect(STRONG, &STRONG_KPS), other.clone()],
+        );
+        assert_detections(
+            &fixture.detect(0.7),
+            &[expect(STRONG, &STRONG_KPS), expect(SHIFTED, &SHIFTED_KPS), other],
+        );
+    }
+
+    #[test]
+    fn scrfd_10_outputs_256() {
+        let fixture = Fixture::load(include_str!("../../tests/fixtures/scrfd/scrfd_10_256x256.json"));
+        // stride 64 and stride 128 faces, both overlap the strong face a little (IoU < 0.2)
+        let stride_64 = ([144.0, 144.0, 240.0, 240.0], 0.7);
+        let stride_128 = ([64.0, 64.0, 192.0, 192.0], 0.6);
+        assert_detections(
+            &fixture.detect(0.4),
+            &[expect(STRONG, &[]), expect(stride_64, &[]), expect(stride_128, &[])],
+        );
+        assert_detections(
+            &fixture.detect(0.7),
+            &[
+                expect(STRONG, &[]),
+                expect(SHIFTED, &[]),
+                expect(stride_64, &[]),
+                expect(stride_128, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn scrfd_15_outputs_384x256_batched() {
+        let fixture = Fixture::load(include_str!("../../tests/fixtures/scrfd/scrfd_15_384x256.json"));
+        let other = expect(
+            ([208.0, 80.0, 304.0, 176.0], 0.7),
+            &[[160.0, 64.0], [352.0, 64.0], [256.0, 160.0], [192.0, 256.0], [320.0, 256.0]],
+        );
+        assert_detections(
+            &fixture.detect(0.4),
+            &[expect(STRONG, &STRONG_KPS), other.clone()],
+        );
+        assert_detections(
+            &fixture.detect(0.7),
+            &[expect(STRONG, &STRONG_KPS), expect(SHIFTED, &SHIFTED_KPS), other],
+        );
+    }
+
+    #[test]
+    fn wrong_input_size_is_an_error() {
+        let fixture = Fixture::load(include_str!("../../tests/fixtures/scrfd/scrfd_6_640x640.json"));
+        let config = ScrfdFaceConfig::from_output_count(6).unwrap();
+        assert!(process_detections(&fixture.outputs(), &config, 320, 320, 0.5, 0.4).is_err());
+        assert!(process_detections(&fixture.outputs()[..4], &config, 640, 640, 0.5, 0.4).is_err());
     }
-    Ok(raw_bboxes)
 }
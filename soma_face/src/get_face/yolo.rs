@@ -1,181 +1,214 @@
-use soma_core::common_utils::{non_maximum_suppression, Bbox};
-use soma_core::onnx_backend::{Inference, InferenceResult, OnnxModel};
-use anyhow::{Error, Result};
-use image::imageops;
-use image::{DynamicImage, GenericImageView};
-use ndarray::{s, Array, ArrayBase, Axis, Dim, IxDyn, OwnedRepr};
-use ort::{
-    inputs, CPUExecutionProvider, ExecutionProvider, GraphOptimizationLevel, Session,
-    SessionOutputs,
-};
+use soma_core::common_utils::{letterbox, non_maximum_suppression, Bbox, Detector, Letterbox};
+use soma_core::onnx_backend::{BackendConfig, Inference, InferenceResult, ModelLoadError, OnnxModel};
+use anyhow::{bail, Error, Result};
+use image::DynamicImage;
+use ndarray::{s, stack, Array, ArrayBase, ArrayViewD, Axis, Dim, OwnedRepr};
 
-#[cfg(feature="tractinference")]
-use tract_data::tensor::Tensor;
+/// number of facial landmarks predicted by yolov8-face
+const YOLO_FACE_KEYPOINTS: usize = 5;
 
-fn scale_wh(w0: f32, h0: f32, w1: f32, h1: f32) -> (f32, f32, f32) {
-    let r = (w1 / w0).min(h1 / h0);
-    (r, (w0 * r).round(), (h0 * r).round())
-}
+/// default IoU threshold for yolo non maximum suppression
+pub const YOLO_NMS_THRESHOLD: f32 = 0.5;
 
-#[cfg(feature="tractinference")]
-pub fn preprocess_face_f32_yolo(
-    image_source: &DynamicImage
-) -> Result<tract_data::tensor::Tensor>{
-    let width = input_image.width();
-    let height = input_image.height();
-    let scale = 640.0 / width.max(height) as f32;
-    let new_width = (width as f32 * scale) as u32;
-    let new_height = (height as f32 * scale) as u32;
-    let resized = image::imageops::resize(&raw_image.to_rgb8(), new_width, new_height, image::imageops::FilterType::Triangle);
-    let mut padded = image::RgbImage::new(640, 640);
-    image::imageops::replace(&mut padded, &resized, (640 - new_width as i64) / 2, (640 - new_height as i64) / 2);
-    
-    tract_ndarray::Array4::from_shape_fn((1, 3, 640, 640), |(_, c, y, x)| {
-        padded.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
-    })
-    .into()
-}
-
-#[cfg(not(feature="tractinference"))]
+/// letterboxes the image into the `width` x `height` model input,
+/// returns the NCHW tensor and the [Letterbox] to map detections back
 pub fn preprocess_face_f32_yolo(
     image_source: &DynamicImage,
-) -> Result<ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>, Error> {
-    let mut preproc: ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>> = Array::ones((1, 3, 640, 640));
-
-    // TODO: refactor to funciton arg 
-    let (_, w_new, h_new) = scale_wh(image_source.width() as f32,
-                                      image_source.height() as f32, 
-                                    640.0,640.0);
-    let img = image_source.resize_exact(w_new as u32, h_new as u32, imageops::FilterType::Triangle);
-    for pixel in img.pixels() {
-        let x = pixel.0 as _;
-        let y = pixel.1 as _;
-        let [r, g, b, _] = pixel.2 .0;
-        preproc[[0, 0, y, x]] = r as f32 / 255.0;
-        preproc[[0, 1, y, x]] = g as f32 / 255.0;
-        preproc[[0, 2, y, x]] = b as f32 / 255.0;
-    }
-    Ok(preproc)
+    width: u32,
+    height: u32,
+) -> Result<(ArrayBase<OwnedRepr<f32>, Dim<[usize; 4]>>, Letterbox), Error> {
+    let (img, letterbox) = letterbox(image_source, width, height);
+    let img = img.to_rgb8();
+    let preproc = Array::from_shape_fn((1, 3, height as usize, width as usize), |(_, c, y, x)| {
+        img.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+    });
+    Ok((preproc, letterbox))
 }
 
 pub struct GetFaceYolo {
     pub onnx_model: OnnxModel,
     pub width: i32,
     pub height: i32,
+    /// IoU above which overlapping detections are suppressed
+    pub nms_threshold: f32,
 }
 
 impl GetFaceYolo {
-    #[cfg(not(feature="tractinference"))]
     pub fn new(
         model_path: &str,
         width: i32,
         height: i32,
         use_fp16: bool,
-    ) -> Result<GetFaceYolo, Error> {
-        Ok(GetFaceYolo {
-            onnx_model: GetFaceYolo::load(model_path, use_fp16),
-            width: width,
-            height: height,
-        })
+    ) -> Result<GetFaceYolo, ModelLoadError> {
+        GetFaceYolo::new_with_backend(model_path, BackendConfig::default(), width, height, use_fp16)
     }
 
-    #[cfg(feature="tractinference")]
-    pub fn new(
+    pub fn new_with_backend(
         model_path: &str,
+        backend: BackendConfig,
         width: i32,
         height: i32,
         use_fp16: bool,
-    ) -> Result<GetFaceYolo, Error> {
+    ) -> Result<GetFaceYolo, ModelLoadError> {
+        let onnx_model = OnnxModel::load_with_backend(
+            backend,
+            model_path,
+            &[1, 3, height as usize, width as usize],
+            use_fp16,
+        )?;
+        GetFaceYolo::validate(&onnx_model, width, height)?;
         Ok(GetFaceYolo {
-            onnx_model: GetFaceYolo::load(model_path, use_fp16),
+            onnx_model,
             width: width,
             height: height,
+            nms_threshold: YOLO_NMS_THRESHOLD,
         })
     }
-}
 
-impl Inference for GetFaceYolo {
-    #[cfg(feature="tractinference")]
-    fn forward(
-        &self,
-        input_image: &DynamicImage,
-        confidence_threshold: f32
-    ) -> Result<InferenceResult, Error> {
-        let preprocess_image = preprocess_face_f32_yolo(input_image);
+    /// single `[1, 3, h, w]` input and a single `[1, 5 (+15), anchors]` output
+    pub fn validate(model: &OnnxModel, width: i32, height: i32) -> Result<(), ModelLoadError> {
+        model.expect_input_shape("yolo", &[1, 3, height as i64, width as i64])?;
+        model.expect_output_count("yolo", &[1])
+    }
 
+    pub fn with_nms_threshold(mut self, nms_threshold: f32) -> GetFaceYolo {
+        self.nms_threshold = nms_threshold;
+        self
     }
 
-    #[cfg(not(feature="tractinference"))]
-    fn forward(
+    /// [Inference::forward] with a per call NMS IoU threshold
+    pub fn detect(
         &self,
         input_image: &DynamicImage,
         confidence_threshold: f32,
-    ) -> Result<InferenceResult, Error> {
-        let preprocess_image = preprocess_face_f32_yolo(input_image)?;
-        let inference = self
-            .onnx_model
-            .model
-            .run(inputs!["images" => preprocess_image.view()]?)?;
-        let _raw_output = inference["output0"]
-            .try_extract_tensor::<f32>()?
-            .view()
-            .t()
-            .into_owned();
-        let (_, w_new, h_new) = scale_wh(input_image.width() as f32,
-                              input_image.height() as f32, 
-                            640.0,640.0);
-        let mut bbox_vec: Vec<Bbox> = vec![];
-        for i in 0.._raw_output.len_of(Axis(0)) {
-            let row = _raw_output.slice(s![i, .., ..]);
-            let confidence = row[[4, 0]];
-            if &confidence >= &confidence_threshold {
-                let x = row[[0, 0]];
-                let y = row[[1, 0]];
-                let w = row[[2, 0]];
-                let h = row[[3, 0]];
+        iou_threshold: f32,
+    ) -> Result<Vec<Bbox>, Error> {
+        let (preprocess_image, letterbox) =
+            preprocess_face_f32_yolo(input_image, self.width as u32, self.height as u32)?;
+        let inference = self.onnx_model.run_f32(preprocess_image.into_dyn().view())?;
+        // single output, `output0`
+        Ok(decode_output(
+            inference[0].view(),
+            &letterbox,
+            confidence_threshold,
+            iou_threshold,
+        ))
+    }
 
-                let x1 = x - w / 2.0;
-                let y1 = y - h / 2.0;
-                let x2 = x + w / 2.0;
-                let y2 = y + h / 2.0;
-                let bbox = Bbox::new(x1, y1, x2, y2, confidence, vec![vec![]]).apply_image_scale(
-                    &input_image,
-                    w_new,
-                    h_new,
-                );
-                bbox_vec.push(bbox);
-            }
-        }
-        Ok(InferenceResult::FaceDetection(non_maximum_suppression(
-            bbox_vec, 0.5,
-        )))
+    /// true if the model was exported with a dynamic batch dimension
+    pub fn supports_batching(&self) -> bool {
+        self.onnx_model
+            .input_dims()
+            .first()
+            .map(|x| *x < 0)
+            .unwrap_or(false)
     }
-    #[cfg(not(feature="tractinference"))]
-    fn load(model_path: &str, fp16: bool) -> OnnxModel {
-        let model: ort::Session = Session::builder()
-            .unwrap()
-            .with_optimization_level(GraphOptimizationLevel::Level3)
-            .unwrap()
-            .with_execution_providers([CPUExecutionProvider::default().build()])
-            .unwrap()
-            .commit_from_file(model_path)
-            .unwrap();
-        OnnxModel {
-            model: model,
-            is_fp16: fp16,
+
+    /// detects faces on several images, `thresholds` are `(confidence, iou)` per image.
+    /// runs as a single NCHW batch when the model allows it, one by one otherwise
+    pub fn detect_batch(
+        &self,
+        input_images: &[DynamicImage],
+        thresholds: &[(f32, f32)],
+    ) -> Result<Vec<Vec<Bbox>>, Error> {
+        if input_images.len() != thresholds.len() {
+            bail!(
+                "got {} images but {} thresholds",
+                input_images.len(),
+                thresholds.len()
+            );
+        }
+        if !self.supports_batching() || input_images.len() == 1 {
+            return input_images
+                .iter()
+                .zip(thresholds)
+                .map(|(img, (conf, iou))| self.detect(img, *conf, *iou))
+                .collect();
         }
+        let preprocessed = input_images
+            .iter()
+            .map(|img| preprocess_face_f32_yolo(img, self.width as u32, self.height as u32))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let views: Vec<_> = preprocessed
+            .iter()
+            .map(|(x, _)| x.index_axis(Axis(0), 0))
+            .collect();
+        let batch = stack(Axis(0), &views)?;
+        let inference = self.onnx_model.run_f32(batch.into_dyn().view())?;
+        Ok(preprocessed
+            .iter()
+            .zip(thresholds)
+            .enumerate()
+            .map(|(n, ((_, letterbox), (conf, iou)))| {
+                decode_output(
+                    inference[0].slice(s![n..n + 1, .., ..]).into_dyn(),
+                    letterbox,
+                    *conf,
+                    *iou,
+                )
+            })
+            .collect())
     }
-    
-    #[cfg(feature="tractinference")]
-    fn load(model_path: &str, fp16: bool) -> OnnxModel {
-        let model = tract_onnx::onnx()
-            .model_for_path(model_path).unwrap()
-            .with_input_fact(0, f32::fact([1,3,640,640]).into())?
-            .into_optimized().unwrap()
-            .into_runnable().unwrap();
-        OnnxModel {
-            model,
-            fp16
+}
+
+/// decodes a single image `[1, 5 (+15), anchors]` yolo output
+/// back into original image coordinates
+fn decode_output(
+    output: ArrayViewD<f32>,
+    letterbox: &Letterbox,
+    confidence_threshold: f32,
+    iou_threshold: f32,
+) -> Vec<Bbox> {
+    let _raw_output = output.t().into_owned();
+    // yolov8-face outputs [x, y, w, h, conf] followed by 5 keypoints as [x, y, visibility]
+    let has_kpss = _raw_output.len_of(Axis(1)) >= 5 + YOLO_FACE_KEYPOINTS * 3;
+    let mut bbox_vec: Vec<Bbox> = vec![];
+    for i in 0.._raw_output.len_of(Axis(0)) {
+        let row = _raw_output.slice(s![i, .., ..]);
+        let confidence = row[[4, 0]];
+        if &confidence >= &confidence_threshold {
+            let x = row[[0, 0]];
+            let y = row[[1, 0]];
+            let w = row[[2, 0]];
+            let h = row[[3, 0]];
+
+            let x1 = x - w / 2.0;
+            let y1 = y - h / 2.0;
+            let x2 = x + w / 2.0;
+            let y2 = y + h / 2.0;
+            let kpss = if has_kpss {
+                (0..YOLO_FACE_KEYPOINTS)
+                    .map(|k| vec![row[[5 + k * 3, 0]], row[[6 + k * 3, 0]]])
+                    .collect()
+            } else {
+                vec![vec![]]
+            };
+            let bbox = Bbox::new(x1, y1, x2, y2, confidence, kpss)
+                .with_detector(Detector::Yolo)
+                .apply_letterbox(letterbox);
+            bbox_vec.push(bbox);
         }
     }
+    non_maximum_suppression(bbox_vec, iou_threshold)
+}
+
+impl Inference for GetFaceYolo {
+    fn forward(
+        &self,
+        input_image: &DynamicImage,
+        confidence_threshold: f32,
+    ) -> Result<InferenceResult, Error> {
+        Ok(InferenceResult::FaceDetection(self.detect(
+            input_image,
+            confidence_threshold,
+            self.nms_threshold,
+        )?))
+    }
+
+    /// loads with the default 640x640 input
+    fn load(model_path: &str, fp16: bool) -> Result<OnnxModel, ModelLoadError> {
+        let model = OnnxModel::load(model_path, &[1, 3, 640, 640], fp16)?;
+        GetFaceYolo::validate(&model, 640, 640)?;
+        Ok(model)
+    }
 }
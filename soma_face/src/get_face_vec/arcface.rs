@@ -1,37 +1,86 @@
 use soma_core::common_utils::image_to_ndarray;
-use soma_core::onnx_backend::{Inference, InferenceResult, OnnxModel};
+use soma_core::onnx_backend::{BackendConfig, Inference, InferenceResult, ModelLoadError, OnnxModel};
 use anyhow::{Error, Result};
 use image::imageops;
 use image::DynamicImage;
-use ndarray::{ArrayBase, Dim, OwnedRepr};
-use ort::{inputs, CPUExecutionProvider, GraphOptimizationLevel, Session};
+use ndarray::{stack, ArrayBase, Axis, Dim, OwnedRepr};
+
+/// length of the face vectors, matches the `vector(512)` column in `soma_db_api`
+pub const ARCFACE_EMBEDDING_SIZE: i64 = 512;
 
 pub struct GetFaceVecArcFace {
     pub onnx_model: OnnxModel,
 }
 
 impl GetFaceVecArcFace {
-    pub fn new(model_path: &str) -> Result<GetFaceVecArcFace, Error> {
-        Ok(GetFaceVecArcFace {
-            onnx_model: GetFaceVecArcFace::load(model_path, false),
-        })
+    pub fn new(model_path: &str) -> Result<GetFaceVecArcFace, ModelLoadError> {
+        GetFaceVecArcFace::new_with_backend(model_path, BackendConfig::default())
+    }
+
+    pub fn new_with_backend(
+        model_path: &str,
+        backend: BackendConfig,
+    ) -> Result<GetFaceVecArcFace, ModelLoadError> {
+        let onnx_model =
+            OnnxModel::load_with_backend(backend, model_path, &[1, 3, 112, 112], false)?;
+        GetFaceVecArcFace::validate(&onnx_model)?;
+        Ok(GetFaceVecArcFace { onnx_model })
+    }
+
+    /// single `[n, 3, 112, 112]` input and a single `[n, 512]` output
+    pub fn validate(model: &OnnxModel) -> Result<(), ModelLoadError> {
+        model.expect_input_shape("arcface", &[-1, 3, 112, 112])?;
+        model.expect_output_count("arcface", &[1])?;
+        model.expect_output_width("arcface", ARCFACE_EMBEDDING_SIZE)
+    }
+
+    /// true if the model was exported with a dynamic batch dimension
+    fn supports_batching(&self) -> bool {
+        self.onnx_model
+            .input_dims()
+            .first()
+            .map(|x| *x < 0)
+            .unwrap_or(false)
+    }
+
+    /// embeds several (aligned) faces, one vector per face in the same order.
+    /// runs as a single NCHW batch when the model allows it, one by one otherwise
+    pub fn forward_batch(&self, input_images: &[DynamicImage]) -> Result<Vec<Vec<f32>>, Error> {
+        if input_images.is_empty() {
+            return Ok(vec![]);
+        }
+        if !self.supports_batching() {
+            return input_images
+                .iter()
+                .map(|img| match self.forward(img, 0.0)? {
+                    InferenceResult::FaceEmbedding(res) => Ok(res),
+                    _ => unreachable!("invalid `InferenceResult`"),
+                })
+                .collect();
+        }
+        let preprocessed = input_images
+            .iter()
+            .map(preprocess_arcface)
+            .collect::<Result<Vec<_>, Error>>()?;
+        let views: Vec<_> = preprocessed
+            .iter()
+            .map(|x| x.index_axis(Axis(0), 0))
+            .collect();
+        let batch = stack(Axis(0), &views)?;
+        let inference = self.onnx_model.run_f32(batch.into_dyn().view())?;
+        // single output, `fc1`
+        Ok(inference[0]
+            .outer_iter()
+            .map(|x| x.iter().copied().collect())
+            .collect())
     }
 }
 
 impl Inference for GetFaceVecArcFace {
-    fn load(model_path: &str, fp16: bool) -> OnnxModel {
-        let model: ort::Session = ort::Session::builder()
-            .unwrap()
-            .with_optimization_level(GraphOptimizationLevel::Level3)
-            .unwrap()
-            .with_execution_providers([CPUExecutionProvider::default().build()])
-            .unwrap()
-            .commit_from_file(model_path)
-            .unwrap();
-        OnnxModel {
-            model: model,
-            is_fp16: fp16,
-        }
+    fn load(model_path: &str, fp16: bool) -> Result<OnnxModel, ModelLoadError> {
+        let model = OnnxModel::load(model_path, &[1, 3, 112, 112], fp16)?;
+        GetFaceVecArcFace::validate(&model)?;
+        Ok(model)
     }
     /// note: confidence is literally not used in this context.
     fn forward(
@@ -41,11 +90,9 @@ impl Inference for GetFaceVecArcFace {
     ) -> Result<InferenceResult, Error> {
         let _ = confidence_threshold;
         let preprocess_image = preprocess_arcface(input_image)?;
-        let inference = self
-            .onnx_model
-            .model
-            .run(inputs!["data" => preprocess_image.view()]?)?;
-        let results = inference["fc1"].try_extract_tensor::<f32>()?.into_owned();
+        let inference = self.onnx_model.run_f32(preprocess_image.into_dyn().view())?;
+        // single output, `fc1`
+        let results = inference[0].as_standard_layout().into_owned();
         Ok(InferenceResult::FaceEmbedding(results.into_raw_vec()))
     }
 }
@@ -62,17 +109,6 @@ fn preprocess_arcface(
     Ok(_final.insert_axis(ndarray::Axis(0)))
 }
 
-pub fn load_arcface(model_path: &str, fp16: bool) -> OnnxModel {
-    let model: ort::Session = Session::builder()
-        .unwrap()
-        .with_optimization_level(GraphOptimizationLevel::Level3)
-        .unwrap()
-        .with_execution_providers([CPUExecutionProvider::default().build()])
-        .unwrap()
-        .commit_from_file(model_path)
-        .unwrap();
-    OnnxModel {
-        model: model,
-        is_fp16: fp16,
-    }
+pub fn load_arcface(model_path: &str, fp16: bool) -> Result<OnnxModel, ModelLoadError> {
+    GetFaceVecArcFace::load(model_path, fp16)
 }
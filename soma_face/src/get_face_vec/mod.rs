@@ -1,3 +1,18 @@
 //! abstraction for getting face vectors
 
 pub mod arcface;
+
+/// cosine similarity of two face vectors (-1 - 1), the vectors don't need to be normalized.
+/// `None` if the lengths differ or a vector is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some((dot / (norm_a * norm_b)).clamp(-1.0, 1.0))
+}
@@ -1,4 +1,9 @@
 pub mod webserver;
-mod get_face;
-mod get_face_vec;
+pub mod config;
+pub mod batching;
+mod align;
+pub mod get_face;
+pub mod get_face_vec;
+pub mod pose;
+pub mod quality;
 
@@ -0,0 +1,67 @@
+//! head pose from the five face keypoints.
+//!
+//! there is no 3d model fit here, the angles come from where the nose sits
+//! between the eyes and the mouth compared to the arcface reference face.
+//! they are rough (a few degrees off around frontal, worse past ~45°) but
+//! monotonic, good enough to keep near-frontal faces or pick the most frontal
+//! frame of a burst
+
+use crate::align::{landmarks_from_kpss, ARCFACE_REFERENCE_LANDMARKS};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// head rotation in degrees, 0 / 0 / 0 is a level face looking at the camera
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HeadPose {
+    /// positive when the nose points to the image right
+    #[schema(example = 4.2)]
+    pub yaw: f32,
+    /// positive when the face looks up
+    #[schema(example = -3.1)]
+    pub pitch: f32,
+    /// positive when the head tilts clockwise in the image
+    #[schema(example = 1.5)]
+    pub roll: f32,
+}
+
+/// estimates the pose from a [soma_core::common_utils::Bbox] `kpss`
+/// (left eye, right eye, nose, left mouth, right mouth),
+/// `None` if the detector has no keypoints or they are degenerate
+pub fn estimate_head_pose(kpss: &[Vec<f32>]) -> Option<HeadPose> {
+    let [left_eye, right_eye, nose, left_mouth, right_mouth] = landmarks_from_kpss(kpss).ok()?;
+
+    // level the eyes first so roll does not leak into yaw / pitch
+    let roll = (right_eye[1] - left_eye[1]).atan2(right_eye[0] - left_eye[0]);
+    let (sin, cos) = (-roll).sin_cos();
+    let level = |[x, y]: [f32; 2]| [x * cos - y * sin, x * sin + y * cos];
+    let (left_eye, right_eye, nose) = (level(left_eye), level(right_eye), level(nose));
+    let (left_mouth, right_mouth) = (level(left_mouth), level(right_mouth));
+
+    let eye_mid = [
+        (left_eye[0] + right_eye[0]) / 2.0,
+        (left_eye[1] + right_eye[1]) / 2.0,
+    ];
+    let half_eye_distance = (right_eye[0] - left_eye[0]) / 2.0;
+    let eye_to_mouth = (left_mouth[1] + right_mouth[1]) / 2.0 - eye_mid[1];
+    if half_eye_distance <= 0.0 || eye_to_mouth <= 0.0 {
+        return None;
+    }
+
+    // the nose moves sideways with yaw, and towards the eyes (up) or mouth (down) with pitch
+    let yaw = ((nose[0] - eye_mid[0]) / half_eye_distance).atan();
+    let nose_position = (nose[1] - eye_mid[1]) / eye_to_mouth;
+    let pitch = (2.0 * (frontal_nose_position() - nose_position)).atan();
+    Some(HeadPose {
+        yaw: yaw.to_degrees(),
+        pitch: pitch.to_degrees(),
+        roll: roll.to_degrees(),
+    })
+}
+
+/// nose height between the eye line (0) and the mouth line (1) on a frontal face
+fn frontal_nose_position() -> f32 {
+    let [left_eye, right_eye, nose, left_mouth, right_mouth] = ARCFACE_REFERENCE_LANDMARKS;
+    let eye_y = (left_eye[1] + right_eye[1]) / 2.0;
+    let mouth_y = (left_mouth[1] + right_mouth[1]) / 2.0;
+    (nose[1] - eye_y) / (mouth_y - eye_y)
+}
@@ -0,0 +1,145 @@
+//! face quality scoring, used to reject blurry, dark or turned faces
+//! before they are enrolled.
+//!
+//! everything is measured on the detected face crop: sharpness as the
+//! variance of the laplacian, mean brightness, the shortest box side and
+//! yaw / pitch from [crate::pose]
+
+use crate::pose::estimate_head_pose;
+use anyhow::{bail, Error, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+use soma_core::common_utils::Bbox;
+use utoipa::ToSchema;
+
+/// faces are resized to this side before measuring sharpness,
+/// so the blur score does not depend on the face size
+const QUALITY_CROP_SIZE: u32 = 112;
+
+/// pass / fail thresholds, a face passes if it clears all of them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    /// minimum laplacian variance of the 112x112 grey face crop
+    pub min_blur: f32,
+    /// mean grey level range (0 - 255)
+    pub min_brightness: f32,
+    pub max_brightness: f32,
+    /// minimum shortest box side in original image pixels
+    pub min_face_size: f32,
+    /// maximum absolute yaw / pitch in degrees,
+    /// only checked when the detector predicts keypoints
+    pub max_yaw: f32,
+    pub max_pitch: f32,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        QualityConfig {
+            min_blur: 60.0,
+            min_brightness: 50.0,
+            max_brightness: 210.0,
+            min_face_size: 64.0,
+            max_yaw: 25.0,
+            max_pitch: 20.0,
+        }
+    }
+}
+
+/// quality of one face, `failures` lists every threshold it missed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct QualityReport {
+    /// laplacian variance, higher is sharper
+    #[schema(example = 180.4)]
+    pub blur: f32,
+    /// mean grey level (0 - 255)
+    #[schema(example = 121.7)]
+    pub brightness: f32,
+    /// shortest box side in pixels
+    #[schema(example = 142.0)]
+    pub face_size: f32,
+    /// degrees, positive when the nose points to the image right,
+    /// missing if the detector has no keypoints
+    #[schema(example = 4.2)]
+    pub yaw: Option<f32>,
+    /// degrees, positive when the face looks up
+    #[schema(example = -3.1)]
+    pub pitch: Option<f32>,
+    pub passed: bool,
+    #[schema(example = json!(["blur"]))]
+    pub failures: Vec<String>,
+}
+
+/// scores the face in `bbox` against `config`
+pub fn assess_face(
+    image: &DynamicImage,
+    bbox: &Bbox,
+    config: &QualityConfig,
+) -> Result<QualityReport, Error> {
+    let face = bbox.crop_bbox(image)?;
+    if face.width() == 0 || face.height() == 0 {
+        bail!("cannot assess an empty face box");
+    }
+    let gray = face
+        .resize_exact(QUALITY_CROP_SIZE, QUALITY_CROP_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let blur = laplacian_variance(&gray);
+    let brightness = mean_brightness(&gray);
+    let face_size = (bbox.x2 - bbox.x1).min(bbox.y2 - bbox.y1);
+    let pose = estimate_head_pose(&bbox.kpss);
+    let (yaw, pitch) = (pose.map(|x| x.yaw), pose.map(|x| x.pitch));
+
+    let mut failures = vec![];
+    if blur < config.min_blur {
+        failures.push(String::from("blur"));
+    }
+    if brightness < config.min_brightness {
+        failures.push(String::from("too_dark"));
+    }
+    if brightness > config.max_brightness {
+        failures.push(String::from("too_bright"));
+    }
+    if face_size < config.min_face_size {
+        failures.push(String::from("face_size"));
+    }
+    if yaw.is_some_and(|x| x.abs() > config.max_yaw) {
+        failures.push(String::from("yaw"));
+    }
+    if pitch.is_some_and(|x| x.abs() > config.max_pitch) {
+        failures.push(String::from("pitch"));
+    }
+    Ok(QualityReport {
+        blur,
+        brightness,
+        face_size,
+        yaw,
+        pitch,
+        passed: failures.is_empty(),
+        failures,
+    })
+}
+
+/// variance of the 4-neighbour laplacian over the inner pixels
+fn laplacian_variance(gray: &GrayImage) -> f32 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let px = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as f32;
+    let mut responses = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            responses
+                .push(px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y));
+        }
+    }
+    let n = responses.len() as f32;
+    let mean = responses.iter().sum::<f32>() / n;
+    responses.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n
+}
+
+fn mean_brightness(gray: &GrayImage) -> f32 {
+    let n = (gray.width() * gray.height()).max(1) as f32;
+    gray.pixels().map(|x| x.0[0] as f32).sum::<f32>() / n
+}
@@ -1,31 +1,54 @@
+mod align;
+mod batching;
+mod config;
 mod get_face;
 mod get_face_vec;
+mod pose;
+mod quality;
 mod webserver;
 
+use actix_multipart::form::MultipartFormConfig;
 use actix_web::middleware::Logger;
 use actix_web::{web, App, HttpServer};
+use anyhow::{Error, Result};
+use batching::{detection_queue, embedding_queue, BatchConfig, DetectionQueue, EmbeddingQueue};
 use clap::Parser;
+use config::{DetectorConfig, FaceApiConfig};
 use dotenvy::dotenv;
 use env_logger;
+use get_face::retinaface::GetFaceRetinaface;
 use get_face::yolo::GetFaceYolo;
 use get_face::FaceExtractor;
 use get_face_vec::arcface::GetFaceVecArcFace;
+use soma_core::onnx_backend::{BackendConfig, BackendKind, ModelLoadError};
 use std::env;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::{SwaggerUi, Url};
+use webserver::blocking::BlockingPool;
+use webserver::error::FaceApiError;
+use webserver::ingest::ImageIngest;
 use webserver::documentation::{
-    GetFaceDocs, GetFaceDocsRetina, GetFaceDocsYolo, GetFaceVecDocsArcFace,
+    FaceQualityDocs, GetFaceDocs, GetFaceDocsRetina, GetFaceDocsYolo, GetFaceVecDocsArcFace,
+    GetFaceVecsDocsArcFace, VerifyDocs,
 };
 use webserver::service::{
     extract_face, 
     get_face_bbox_retinaface, 
     get_face_bbox_yolo,
+    get_face_quality,
     get_face_vectors,
-    get_largest_face, index
+    get_face_vectors_multi,
+    get_largest_face, index,
+    verify_faces,
 };
 
 #[derive(clap::Parser)]
 struct CliArgs {
+    /// toml or yaml config file,
+    /// defaults to `SOMA_FACE_CONFIG` or `./soma_face.toml` if it exists
+    #[arg(long)]
+    config: Option<String>,
+
     /// number of workers for server
     /// defaults to 4 if none is specified
     #[arg(long)]
@@ -40,6 +63,59 @@ struct CliArgs {
     /// defaults to false
     #[arg(long)]
     only_detect: Option<bool>,
+
+    /// inference backend for the face detectors (`ort` or `tract`)
+    /// defaults to `ort` if compiled in
+    #[arg(long)]
+    detect_backend: Option<BackendKind>,
+
+    /// inference backend for the face vector model (`ort` or `tract`)
+    /// defaults to `ort` if compiled in
+    #[arg(long)]
+    vec_backend: Option<BackendKind>,
+
+    /// intra-op threads per face detector session,
+    /// defaults to letting the backend decide
+    #[arg(long)]
+    detect_threads: Option<usize>,
+
+    /// intra-op threads for the face vector session,
+    /// defaults to letting the backend decide
+    #[arg(long)]
+    vec_threads: Option<usize>,
+}
+
+impl CliArgs {
+    /// cli args take precedence over the config file and the environment
+    fn apply(&self, config: &mut FaceApiConfig) {
+        if let Some(workers) = self.workers {
+            config.workers = workers as usize;
+        }
+        if let Some(force_yolo) = self.force_yolo {
+            config.enable_retinaface(!force_yolo);
+        }
+        if let Some(only_detect) = self.only_detect {
+            config.enable_arcface(!only_detect);
+        }
+        let detectors = std::iter::once(&mut config.models.yolo)
+            .chain(config.models.retinaface.as_mut());
+        for detector in detectors {
+            if let Some(backend) = self.detect_backend {
+                detector.backend = backend;
+            }
+            if self.detect_threads.is_some() {
+                detector.threads = self.detect_threads;
+            }
+        }
+        if let Some(arcface) = config.models.arcface.as_mut() {
+            if let Some(backend) = self.vec_backend {
+                arcface.backend = backend;
+            }
+            if self.vec_threads.is_some() {
+                arcface.threads = self.vec_threads;
+            }
+        }
+    }
 }
 
 #[actix_web::main]
@@ -49,158 +125,245 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     let args = CliArgs::parse();
 
-    let workers = match &args.workers {
-        Some(ref _i32) => args.workers,
-        None => Some(4),
-    };
-    let force_yolo = match &args.force_yolo {
-        Some(ref _bool) => match _bool.to_owned() {
-            true => {
-                println!("face detector configured to use yolo only");
-                args.force_yolo
-            }
-            false => {
-                println!("face detector configured to use yolo and retinaface");
-                args.force_yolo
-            }
-        },
-        None => {
-            println!("face detector configured to use yolo only");
-            Some(true)
+    let mut config = match FaceApiConfig::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("cannot start soma_face: {:#}", e);
+            std::process::exit(1);
         }
+    };
+    args.apply(&mut config);
+    match &config.models.retinaface {
+        Some(_) => println!("face detector configured to use yolo and retinaface"),
+        None => println!("face detector configured to use yolo only"),
     }
-    .unwrap();
-    
-
-
-    let only_detect = match &args.only_detect {
-        Some(ref _bool) => match _bool.to_owned() {
-            true => {
-                println!("loading face detector route");
-                args.only_detect
-            }
-            false => {
-                println!("loading face detector route");
-                args.only_detect
-            }
-        },
-        None => {
-            println!("loading face detector and face vector routes");
-            Some(false)
-        }
+    match config.serves_vectors() {
+        true => println!("loading face detector and face vector routes"),
+        false => println!("loading face detector route"),
     }
-    .unwrap();
-
 
     let server_address = env::var("SERVER_ADDRESS").expect("cannot read server address");
     let server_port = env::var("SERVER_PORT").expect("cannot read server port");
     let bind_addr = format!("{}:{}", server_address, server_port);
 
     env_logger::init();
-    let arcface_model_path = "./models/arcfaceresnet100-8.onnx";
-    let retina_model_path = "./models/det_10g.onnx";
-    let yolo_model_path = "./models/yoloface_8n.onnx";
     print_splash();
-    println!(
-        "starting server with {:?} workers",
-        &workers.unwrap().to_owned()
-    );
-    HttpServer::new(move || {
-        if force_yolo == false {
-            match only_detect {
-                true => {
-                    let face_extractor = web::Data::new(
-                        FaceExtractor::new(retina_model_path, yolo_model_path, 640, 640).unwrap(),
-                    );
-                    App::new()
-                        .service(index)
-                        .service(extract_face)
-                        .service(get_largest_face)
-                        .app_data(face_extractor)
-
-                        // the docs section
-                        .service(SwaggerUi::new("/docs/{_:.*}").urls(vec![
-                            (Url::new("get_face", "/get_face"), GetFaceDocs::openapi()),
-                        ]))
-                        .wrap(Logger::default())
-                }, 
-                false => {
-                    let face_arc = web::Data::new(GetFaceVecArcFace::new(arcface_model_path).unwrap());
-                    let face_extractor = web::Data::new(
-                        FaceExtractor::new(retina_model_path, yolo_model_path, 640, 640).unwrap(),
-                    );
-                    App::new()
-                        .service(index)
-                        .service(extract_face)
-                        .service(get_largest_face)
-                        .app_data(face_extractor)
-                        .service(get_face_vectors)
-                        .app_data(face_arc)
-                        // the docs section
-                        .service(SwaggerUi::new("/docs/{_:.*}").urls(vec![
-                            (Url::new("get_face", "/get_face"), GetFaceDocs::openapi()),
-                            (
-                                Url::new("get_face_vec", "/get_vec"),
-                                GetFaceVecDocsArcFace::openapi(),
-                            ),
-                        ]))
-                        .wrap(Logger::default())
-                }
+
+    // every model is loaded once here and shared by all workers,
+    // `web::Data` is an `Arc` so cloning it into each worker is cheap.
+    // a missing file or an unexpected export fails here with a readable
+    // message instead of a panic in each worker thread
+    let models = match load_models(&config) {
+        Ok(models) => models,
+        Err(e) => {
+            eprintln!("cannot start soma_face: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let queues = match spawn_queues(&models, config.batching) {
+        Ok(queues) => queues,
+        Err(e) => {
+            eprintln!("cannot start soma_face: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    // one pool for every worker so `max_pending` bounds the whole server
+    let pool = web::Data::new(BlockingPool::new(config.blocking));
+    let workers = config.workers;
+    let routes = config.routes.clone();
+    let ingest_config = config.ingest.clone();
+    let quality_config = web::Data::new(config.quality);
+    let verify_config = web::Data::new(config.verify);
+    // raw and multipart bodies carry the image as is, base64 json grows it by a third.
+    // `/verify` takes two images, every single image is still checked against `max_bytes`
+    let max_body_bytes = 2 * ingest_config.max_bytes + 64 * 1024;
+    let max_json_bytes = 2 * (ingest_config.max_bytes / 3 * 4) + 64 * 1024;
+    println!("starting server with {:?} workers", workers);
+    let mut server = HttpServer::new(move || {
+        // reqwest's connection pool is tied to the worker runtime, one client per worker
+        let ingest = ImageIngest::new(ingest_config.clone())
+            .expect("cannot build the image url client");
+        // malformed forms and queries get the same json error body as the handlers
+        let form_config = MultipartFormConfig::default()
+            .total_limit(max_body_bytes)
+            .error_handler(FaceApiError::from_multipart);
+        let json_config = web::JsonConfig::default()
+            .limit(max_json_bytes)
+            .error_handler(|err, _| FaceApiError::InvalidRequest(err.to_string()).into());
+        let query_config = web::QueryConfig::default()
+            .error_handler(|err, _| FaceApiError::InvalidRequest(err.to_string()).into());
+        let mut app = App::new()
+            .service(index)
+            .app_data(pool.clone())
+            .app_data(web::Data::new(ingest))
+            .app_data(quality_config.clone())
+            .app_data(form_config)
+            .app_data(json_config)
+            .app_data(query_config)
+            .app_data(web::PayloadConfig::new(max_body_bytes))
+            .wrap(Logger::default());
+        let mut docs = vec![];
+        if let Some(yolo_queue) = &queues.yolo {
+            app = app.app_data(yolo_queue.clone());
+        }
+        if let Some(arcface_queue) = &queues.arcface {
+            app = app.app_data(arcface_queue.clone());
+        }
+        if let Some(face_extractor) = &models.face_extractor {
+            app = app.app_data(face_extractor.clone());
+            if routes.detect {
+                app = app
+                    .service(extract_face)
+                    .service(get_face_bbox_retinaface);
+                docs.push((Url::new("get_face", "/get_face"), GetFaceDocs::openapi()));
+                docs.push((
+                    Url::new("get_face_retina", "/get_face/retina"),
+                    GetFaceDocsRetina::openapi(),
+                ));
             }
-        } else {
-            match only_detect { 
-                true => { 
-                    let face_extractor = web::Data::new(GetFaceYolo::new(yolo_model_path, 640, 640, false).unwrap());
-                    App::new()
-                        .service(index)
-                        .service(get_face_bbox_yolo)
-                        .service(get_largest_face)
-                        .app_data(face_extractor)
-                        // the docs section
-                        .service(SwaggerUi::new("/docs/{_:.*}").urls(vec![
-                            (
-                                Url::new("get_face_yolo", "/get_face"),
-                                GetFaceDocsYolo::openapi(),
-                            ),
-                        ]))
-                        .wrap(Logger::default())
-                },
-                false => {
-                    let face_arc = web::Data::new(GetFaceVecArcFace::new(arcface_model_path).unwrap());
-                    let face_extractor =
-                        web::Data::new(GetFaceYolo::new(yolo_model_path, 640, 640, false).unwrap());
-                    App::new()
-                        .service(index)
-                        .service(get_face_bbox_yolo)
-                        .service(get_largest_face)
-                        .app_data(face_extractor)
-                        .service(get_face_vectors)
-                        .app_data(face_arc)
-                        // the docs section
-                        .service(SwaggerUi::new("/docs/{_:.*}").urls(vec![
-                            (
-                                Url::new("get_face_yolo", "/get_face"),
-                                GetFaceDocsYolo::openapi(),
-                            ),
-                            (
-                                Url::new("get_face_vec", "/get_vec"),
-                                GetFaceVecDocsArcFace::openapi(),
-                            ),
-                        ]))
-                        .wrap(Logger::default())
-                }
+        }
+        if let Some(yolo) = &models.yolo {
+            app = app.app_data(yolo.clone());
+            if routes.detect {
+                app = app.service(get_face_bbox_yolo);
+                docs.push((
+                    Url::new("get_face_yolo", "/get_face"),
+                    GetFaceDocsYolo::openapi(),
+                ));
             }
         }
+        if routes.largest_face {
+            app = app.service(get_largest_face);
+        }
+        if routes.quality {
+            app = app.service(get_face_quality);
+            docs.push((
+                Url::new("face_quality", "/face_quality"),
+                FaceQualityDocs::openapi(),
+            ));
+        }
+        if let Some(face_arc) = &models.arcface {
+            app = app
+                .app_data(face_arc.clone())
+                .app_data(verify_config.clone())
+                .service(get_face_vectors)
+                .service(get_face_vectors_multi)
+                .service(verify_faces);
+            docs.push((
+                Url::new("get_face_vec", "/get_vec"),
+                GetFaceVecDocsArcFace::openapi(),
+            ));
+            docs.push((
+                Url::new("get_face_vecs", "/get_vecs"),
+                GetFaceVecsDocsArcFace::openapi(),
+            ));
+            docs.push((Url::new("verify", "/verify"), VerifyDocs::openapi()));
+        }
+        // the docs section
+        if routes.docs {
+            app = app.service(SwaggerUi::new("/docs/{_:.*}").urls(docs));
+        }
+        app
     })
     .client_request_timeout(std::time::Duration::from_secs(0))
     .keep_alive(None)
-    .bind(&bind_addr)?
-    .workers(workers.unwrap() as usize)
-    .run()
-    .await
+    .workers(workers);
+    if let Some(threads) = config.blocking.threads {
+        server = server.worker_max_blocking_threads(threads);
+    }
+    server.bind(&bind_addr)?.run().await
+}
+
+
+
+/// models shared by every worker,
+/// either `face_extractor` (yolo + retinaface) or `yolo` alone is loaded
+#[derive(Clone)]
+struct LoadedModels {
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo: Option<web::Data<GetFaceYolo>>,
+    arcface: Option<web::Data<GetFaceVecArcFace>>,
 }
 
+fn load_detector<T>(
+    config: &DetectorConfig,
+    load: fn(&str, BackendConfig, i32, i32, bool) -> Result<T, ModelLoadError>,
+) -> Result<T, ModelLoadError> {
+    load(
+        &config.path,
+        config.backend_config(),
+        config.width,
+        config.height,
+        false,
+    )
+}
 
+/// loads (and validates) the models the configured routes need
+fn load_models(config: &FaceApiConfig) -> Result<LoadedModels, ModelLoadError> {
+    let load_time = std::time::Instant::now();
+    let yolo_config = &config.models.yolo;
+    let mut yolo = load_detector(yolo_config, GetFaceYolo::new_with_backend)?;
+    if let Some(nms_threshold) = yolo_config.nms_threshold {
+        yolo = yolo.with_nms_threshold(nms_threshold);
+    }
+    let (face_extractor, yolo) = match &config.models.retinaface {
+        None => (None, Some(web::Data::new(yolo))),
+        Some(retina_config) => {
+            let mut retina = load_detector(retina_config, GetFaceRetinaface::new_with_backend)?;
+            if let Some(nms_threshold) = retina_config.nms_threshold {
+                retina = retina.with_nms_threshold(nms_threshold);
+            }
+            let face_extractor = FaceExtractor::from_models(retina, yolo);
+            (Some(web::Data::new(face_extractor)), None)
+        }
+    };
+    let arcface = match (&config.models.arcface, config.serves_vectors()) {
+        (Some(arcface_config), true) => Some(web::Data::new(GetFaceVecArcFace::new_with_backend(
+            &arcface_config.path,
+            arcface_config.backend_config(),
+        )?)),
+        _ => None,
+    };
+    println!("models loaded and validated in {:?}", load_time.elapsed());
+    Ok(LoadedModels {
+        face_extractor,
+        yolo,
+        arcface,
+    })
+}
+
+/// batch queues in front of yolo and arcface, shared by every worker
+#[derive(Clone, Default)]
+struct BatchQueues {
+    yolo: Option<web::Data<DetectionQueue>>,
+    arcface: Option<web::Data<EmbeddingQueue>>,
+}
+
+fn spawn_queues(models: &LoadedModels, config: BatchConfig) -> Result<BatchQueues, Error> {
+    if !config.enabled {
+        return Ok(BatchQueues::default());
+    }
+    let yolo = match (&models.yolo, &models.face_extractor) {
+        (Some(yolo), _) => Some(yolo.clone().into_inner()),
+        (None, Some(extractor)) => Some(extractor.yolo_model.clone()),
+        (None, None) => None,
+    };
+    let yolo = yolo
+        .map(|model| detection_queue(model, config))
+        .transpose()?
+        .map(web::Data::new);
+    let arcface = models
+        .arcface
+        .as_ref()
+        .map(|model| embedding_queue(model.clone().into_inner(), config))
+        .transpose()?
+        .map(web::Data::new);
+    println!(
+        "batching up to {} requests per model within {}ms",
+        config.max_batch_size, config.max_wait_ms
+    );
+    Ok(BatchQueues { yolo, arcface })
+}
 
 fn print_splash() {
 let splash: &str = r#"
@@ -0,0 +1,71 @@
+//! bounded pool for image decoding and inference.
+//!
+//! handlers hand their cpu bound work to [BlockingPool::run] so the actix
+//! event loop only parses requests and writes responses. at most
+//! `max_pending` jobs are queued or running at once, past that requests are
+//! turned away with a 503 and a `Retry-After` header instead of piling up
+
+use crate::webserver::error::FaceApiError;
+use actix_web::web;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockingConfig {
+    /// jobs queued or running at once across every worker
+    pub max_pending: usize,
+    /// value of the `Retry-After` header on a 503
+    pub retry_after_secs: u64,
+    /// blocking threads per worker, unset keeps the actix default
+    pub threads: Option<usize>,
+}
+
+impl Default for BlockingConfig {
+    fn default() -> Self {
+        BlockingConfig {
+            max_pending: 64,
+            retry_after_secs: 1,
+            threads: None,
+        }
+    }
+}
+
+/// shared by every worker through `web::Data`
+pub struct BlockingPool {
+    permits: Arc<Semaphore>,
+    retry_after_secs: u64,
+}
+
+impl BlockingPool {
+    pub fn new(config: BlockingConfig) -> BlockingPool {
+        BlockingPool {
+            permits: Arc::new(Semaphore::new(config.max_pending.max(1))),
+            retry_after_secs: config.retry_after_secs,
+        }
+    }
+
+    /// runs `job` on the actix blocking threads,
+    /// [FaceApiError::Overloaded] if the pool is full
+    pub async fn run<F, T>(&self, job: F) -> Result<T, FaceApiError>
+    where
+        F: FnOnce() -> Result<T, FaceApiError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = self
+            .permits
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| FaceApiError::Overloaded {
+                retry_after_secs: self.retry_after_secs,
+            })?;
+        web::block(move || {
+            // released once the job is done, even if the request was dropped
+            let _permit = permit;
+            job()
+        })
+        .await
+        .map_err(|e| FaceApiError::Internal(e.to_string()))?
+    }
+}
@@ -1,18 +1,27 @@
+use crate::webserver::error::FaceApiError;
 use actix_multipart::form::tempfile::TempFile;
 use base64::{engine::general_purpose, Engine as _};
-use image::{DynamicImage,ImageFormat, ImageReader};
+use image::{DynamicImage, ImageFormat};
+use soma_core::image_ingest::{decode_image, IngestConfig, IngestError};
 use std::io::{Cursor, Read};
 
-/// turns a [TempFile] from [actix_multipart::Multipart] into a [DynamicImage]
-pub fn tempfile_to_dynimg(input_tempfile: TempFile) -> actix_web::Result<DynamicImage> {
+/// turns a [TempFile] from [actix_multipart::Multipart] into a [DynamicImage],
+/// non image and corrupt uploads are reported as [FaceApiError]s
+pub fn tempfile_to_dynimg(
+    input_tempfile: TempFile,
+    config: &IngestConfig,
+) -> Result<DynamicImage, FaceApiError> {
+    // the form limit covers every file of the form, check this one before reading it
+    if input_tempfile.size > config.max_bytes {
+        return Err(IngestError::TooLarge {
+            limit: config.max_bytes,
+        }
+        .into());
+    }
     let mut file = input_tempfile.file;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
-    let img = ImageReader::new(Cursor::new(buffer))
-        .with_guessed_format()?
-        .decode()
-        .unwrap();
-    Ok(img)
+    Ok(decode_image(&buffer, config)?)
 }
 
 
@@ -1,34 +1,60 @@
+use crate::pose::HeadPose;
+use crate::quality::QualityReport;
 use crate::webserver;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::{SwaggerUi, Url};
 use webserver::handler::{
-    GetFaceRequest, GetFaceResponse, GetFaceResponseNone, GetFaceVecRequest, GetFaceVecResponse,
+    DetectorChoice, ErrorResponse, FaceJsonRequest, FaceQualityResponse, FaceRequestFields, FaceEmbeddingResponse, FaceResponse, FaceResponseV2, GetFaceRequest, GetFaceResponse,
+    GetFaceResponseNone, GetFaceResponseV2, GetFaceVecRequest, GetFaceVecResponse,
+    GetFaceVecsRequest, GetFaceVecsResponse, GetLargestFaceRequest, NormalizedBox, VerifyJsonRequest,
+    VerifyRequest, VerifyResponse,
 };
 
 #[derive(OpenApi)]
 #[openapi(
     paths(crate::webserver::service::get_face_bbox_yolo),
-    components(schemas(GetFaceRequest, GetFaceResponse))
+    components(schemas(GetFaceRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, GetFaceResponse, ErrorResponse, GetFaceResponseNone, FaceResponse, GetFaceResponseV2, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
 )]
 pub struct GetFaceDocsYolo;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(crate::webserver::service::get_face_bbox_retinaface),
-    components(schemas(GetFaceRequest, GetFaceResponse, GetFaceResponseNone))
+    components(schemas(GetFaceRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, GetFaceResponse, ErrorResponse, GetFaceResponseNone, FaceResponse, GetFaceResponseV2, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
 )]
 pub struct GetFaceDocsRetina;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(crate::webserver::service::get_face_vectors),
-    components(schemas(GetFaceVecRequest, GetFaceVecResponse))
+    components(schemas(GetFaceVecRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, GetFaceVecResponse, ErrorResponse, GetFaceResponseNone, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
 )]
 pub struct GetFaceVecDocsArcFace;
 
+#[derive(OpenApi)]
+#[openapi(
+    paths(crate::webserver::service::get_face_vectors_multi),
+    components(schemas(GetFaceVecsRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, GetFaceVecsResponse, GetFaceResponseNone, ErrorResponse, FaceEmbeddingResponse, HeadPose))
+)]
+pub struct GetFaceVecsDocsArcFace;
+
 #[derive(OpenApi)]
 #[openapi(
     paths(crate::webserver::service::extract_face),
-    components(schemas(GetFaceRequest, GetFaceResponse, GetFaceResponseNone))
+    components(schemas(GetFaceRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, GetFaceResponse, ErrorResponse, GetFaceResponseNone, FaceResponse, GetFaceResponseV2, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
 )]
 pub struct GetFaceDocs;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(crate::webserver::service::get_face_quality),
+    components(schemas(GetLargestFaceRequest, FaceJsonRequest, FaceRequestFields, DetectorChoice, FaceQualityResponse, ErrorResponse, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
+)]
+pub struct FaceQualityDocs;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(crate::webserver::service::verify_faces),
+    components(schemas(VerifyRequest, VerifyJsonRequest, FaceRequestFields, DetectorChoice, VerifyResponse, ErrorResponse, FaceResponseV2, NormalizedBox, HeadPose, QualityReport))
+)]
+pub struct VerifyDocs;
@@ -0,0 +1,440 @@
+//! errors returned by the face routes.
+//!
+//! every error is answered with an [ErrorResponse] json body, `code` is stable
+//! and meant for clients to match on, `message` is for humans and may change
+
+use crate::webserver::handler::ErrorResponse;
+use crate::webserver::ingest::ImageIngest;
+use actix_multipart::MultipartError;
+use actix_web::error::PayloadError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use soma_core::image_ingest::{IngestError, DEFAULT_MAX_IMAGE_BYTES};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum FaceApiError {
+    /// the upload is not an image format we can decode
+    UnsupportedFormat(String),
+    /// the upload is over the size limit, in bytes
+    ImageTooLarge { limit: usize },
+    /// the image dimensions are over the pixel limits, shares the `image_too_large` code
+    TooManyPixels { width: u32, height: u32 },
+    /// the upload claims to be an image but cannot be decoded
+    CorruptImage(String),
+    /// no face over the confidence threshold
+    NoFace,
+    /// a request field is missing or invalid
+    InvalidRequest(String),
+    /// url inputs are disabled or the host is not allowlisted
+    UrlNotAllowed(String),
+    /// the image url could not be fetched
+    FetchFailed(String),
+    /// the requested detector / model is not loaded on this server
+    ModelUnavailable(String),
+    /// inference or pre / post processing failed
+    ModelFailure(anyhow::Error),
+    /// the blocking pool is full
+    Overloaded { retry_after_secs: u64 },
+    /// anything else, the blocking pool shutting down, io ..
+    Internal(String),
+}
+
+impl FaceApiError {
+    /// stable error code sent in [ErrorResponse::code]
+    pub fn code(&self) -> &'static str {
+        match self {
+            FaceApiError::UnsupportedFormat(_) => "unsupported_format",
+            FaceApiError::ImageTooLarge { .. } | FaceApiError::TooManyPixels { .. } => {
+                "image_too_large"
+            }
+            FaceApiError::CorruptImage(_) => "corrupt_image",
+            FaceApiError::NoFace => "no_face",
+            FaceApiError::InvalidRequest(_) => "invalid_request",
+            FaceApiError::UrlNotAllowed(_) => "url_not_allowed",
+            FaceApiError::FetchFailed(_) => "fetch_failed",
+            FaceApiError::ModelUnavailable(_) => "model_unavailable",
+            FaceApiError::ModelFailure(_) => "model_failure",
+            FaceApiError::Overloaded { .. } => "overloaded",
+            FaceApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// error handler for `MultipartFormConfig`, so malformed forms get the same body
+    pub fn from_multipart(err: MultipartError, req: &HttpRequest) -> actix_web::Error {
+        match err {
+            MultipartError::Payload(PayloadError::Overflow) => FaceApiError::ImageTooLarge {
+                limit: req
+                    .app_data::<web::Data<ImageIngest>>()
+                    .map(|x| x.config().max_bytes)
+                    .unwrap_or(DEFAULT_MAX_IMAGE_BYTES),
+            },
+            err => FaceApiError::InvalidRequest(err.to_string()),
+        }
+        .into()
+    }
+}
+
+impl fmt::Display for FaceApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaceApiError::UnsupportedFormat(reason) => {
+                write!(f, "the uploaded file is not a supported image format: {}", reason)
+            }
+            FaceApiError::ImageTooLarge { limit } => {
+                write!(f, "the upload is larger than the {} bytes limit", limit)
+            }
+            FaceApiError::TooManyPixels { width, height } => write!(
+                f,
+                "the image is {}x{} pixels, which is over the server limits",
+                width, height
+            ),
+            FaceApiError::CorruptImage(reason) => {
+                write!(f, "the uploaded image cannot be decoded: {}", reason)
+            }
+            FaceApiError::NoFace => write!(
+                f,
+                "no detections were found, please try with a better image!"
+            ),
+            FaceApiError::InvalidRequest(reason) => write!(f, "{}", reason),
+            FaceApiError::UrlNotAllowed(url) => {
+                write!(f, "fetching images from `{}` is not allowed", url)
+            }
+            FaceApiError::FetchFailed(reason) => write!(f, "cannot fetch the image: {}", reason),
+            FaceApiError::ModelUnavailable(reason) => write!(f, "{}", reason),
+            FaceApiError::ModelFailure(source) => write!(f, "inference failed: {:#}", source),
+            FaceApiError::Overloaded { .. } => write!(f, "server is busy, please retry later"),
+            FaceApiError::Internal(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FaceApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FaceApiError::ModelFailure(source) => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for FaceApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FaceApiError::UnsupportedFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FaceApiError::ImageTooLarge { .. } | FaceApiError::TooManyPixels { .. } => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            FaceApiError::CorruptImage(_)
+            | FaceApiError::NoFace
+            | FaceApiError::InvalidRequest(_)
+            | FaceApiError::UrlNotAllowed(_)
+            | FaceApiError::ModelUnavailable(_) => StatusCode::BAD_REQUEST,
+            FaceApiError::FetchFailed(_) => StatusCode::BAD_GATEWAY,
+            FaceApiError::Overloaded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            FaceApiError::ModelFailure(_) | FaceApiError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let FaceApiError::Overloaded { retry_after_secs } = self {
+            response.insert_header((header::RETRY_AFTER, retry_after_secs.to_string()));
+        }
+        response.json(ErrorResponse {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        })
+    }
+}
+
+/// model calls return `anyhow` errors, `?` turns them into [FaceApiError::ModelFailure]
+impl From<anyhow::Error> for FaceApiError {
+    fn from(err: anyhow::Error) -> Self {
+        FaceApiError::ModelFailure(err)
+    }
+}
+
+impl From<IngestError> for FaceApiError {
+    fn from(err: IngestError) -> Self {
+        match err {
+            IngestError::TooLarge { limit } => FaceApiError::ImageTooLarge { limit },
+            IngestError::TooManyPixels { width, height } => {
+                FaceApiError::TooManyPixels { width, height }
+            }
+            IngestError::UnsupportedFormat(reason) => FaceApiError::UnsupportedFormat(reason),
+            IngestError::UrlNotAllowed(url) => FaceApiError::UrlNotAllowed(url),
+            IngestError::Fetch(reason) => FaceApiError::FetchFailed(reason),
+            IngestError::InvalidBase64(_) => FaceApiError::InvalidRequest(err.to_string()),
+            IngestError::Empty | IngestError::Corrupt(_) => {
+                FaceApiError::CorruptImage(err.to_string())
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for FaceApiError {
+    fn from(err: std::io::Error) -> Self {
+        FaceApiError::Internal(format!("cannot read the upload: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! bad uploads sent to every face route. the models are stubs that fail the test if
+    //! they are ever run, every one of these errors must be answered before inference
+
+    use super::*;
+    use crate::config::VerifyConfig;
+    use crate::get_face::retinaface::{GetFaceRetinaface, ScrfdFaceConfig};
+    use crate::get_face::yolo::GetFaceYolo;
+    use crate::get_face::FaceExtractor;
+    use crate::get_face_vec::arcface::GetFaceVecArcFace;
+    use crate::quality::QualityConfig;
+    use crate::webserver::blocking::{BlockingConfig, BlockingPool};
+    use crate::webserver::service::{
+        extract_face, get_face_bbox_retinaface, get_face_bbox_yolo, get_face_quality,
+        get_face_vectors, get_face_vectors_multi, get_largest_face, index, verify_faces,
+    };
+    use actix_multipart::form::MultipartFormConfig;
+    use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+    use actix_web::{test, App};
+    use anyhow::{bail, Error};
+    use ndarray::{ArrayD, ArrayViewD};
+    use soma_core::image_ingest::IngestConfig;
+    use soma_core::onnx_backend::{BackendKind, InferenceBackend, OnnxModel, TensorInfo};
+    use std::io::Cursor;
+
+    const MAX_BYTES: usize = 16 * 1024;
+    const BOUNDARY: &str = "soma-face-test-boundary";
+
+    #[derive(Debug)]
+    struct StubBackend {
+        inputs: Vec<TensorInfo>,
+        outputs: Vec<TensorInfo>,
+    }
+
+    impl InferenceBackend for StubBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::default_available()
+        }
+
+        fn inputs(&self) -> &[TensorInfo] {
+            &self.inputs
+        }
+
+        fn outputs(&self) -> &[TensorInfo] {
+            &self.outputs
+        }
+
+        fn run(&self, _inputs: &[(&str, ArrayViewD<f32>)]) -> Result<Vec<ArrayD<f32>>, Error> {
+            bail!("the stub model was run")
+        }
+    }
+
+    fn stub_model(input: &[i64], output_count: usize) -> OnnxModel {
+        let tensor = |name: String, shape: Vec<i64>| TensorInfo { name, shape };
+        let backend = StubBackend {
+            inputs: vec![tensor(String::from("input"), input.to_vec())],
+            outputs: (0..output_count)
+                .map(|x| tensor(format!("output_{}", x), vec![-1]))
+                .collect(),
+        };
+        OnnxModel::new(Box::new(backend), false).unwrap()
+    }
+
+    fn stub_yolo() -> GetFaceYolo {
+        GetFaceYolo {
+            onnx_model: stub_model(&[1, 3, 640, 640], 1),
+            width: 640,
+            height: 640,
+            nms_threshold: 0.5,
+        }
+    }
+
+    fn stub_face_extractor() -> FaceExtractor {
+        let retina_model = GetFaceRetinaface {
+            onnx_model: stub_model(&[1, 3, 640, 640], 9),
+            width: 640,
+            height: 640,
+            nms_threshold: 0.4,
+            config: ScrfdFaceConfig::from_output_count(9).unwrap(),
+        };
+        FaceExtractor::from_models(retina_model, stub_yolo())
+    }
+
+    /// the server app (limits and error handlers included) with every face route,
+    /// `/get_face` is served by the retinaface + yolo extractor when `extractor` is set,
+    /// which also adds `/get_face/retina`
+    fn face_app(
+        extractor: bool,
+    ) -> App<
+        impl ServiceFactory<
+            ServiceRequest,
+            Config = (),
+            Response = ServiceResponse,
+            Error = actix_web::Error,
+            InitError = (),
+        >,
+    > {
+        let ingest_config = IngestConfig {
+            max_bytes: MAX_BYTES,
+            ..IngestConfig::default()
+        };
+        let max_body_bytes = 2 * ingest_config.max_bytes + 64 * 1024;
+        let form_config = MultipartFormConfig::default()
+            .total_limit(max_body_bytes)
+            .error_handler(FaceApiError::from_multipart);
+        let mut app = App::new()
+            .service(index)
+            .app_data(web::Data::new(BlockingPool::new(BlockingConfig::default())))
+            .app_data(web::Data::new(ImageIngest::new(ingest_config).unwrap()))
+            .app_data(web::Data::new(QualityConfig::default()))
+            .app_data(web::Data::new(VerifyConfig::default()))
+            .app_data(form_config)
+            .app_data(web::PayloadConfig::new(max_body_bytes))
+            .app_data(web::Data::new(stub_yolo()))
+            .app_data(web::Data::new(GetFaceVecArcFace {
+                onnx_model: stub_model(&[-1, 3, 112, 112], 1),
+            }));
+        app = match extractor {
+            true => app
+                .app_data(web::Data::new(stub_face_extractor()))
+                .service(extract_face)
+                .service(get_face_bbox_retinaface),
+            false => app.service(get_face_bbox_yolo),
+        };
+        app.service(get_largest_face)
+            .service(get_face_quality)
+            .service(get_face_vectors)
+            .service(get_face_vectors_multi)
+            .service(verify_faces)
+    }
+
+    /// `(route, file fields of its form)`
+    const FACE_ROUTES: &[(&str, &[&str])] = &[
+        ("/get_face", &["input"]),
+        ("/get_largest_face", &["input"]),
+        ("/face_quality", &["input"]),
+        ("/get_vec", &["input"]),
+        ("/get_vecs", &["input"]),
+        ("/verify", &["first", "second"]),
+    ];
+
+    /// only served when retinaface is loaded
+    const RETINA_ROUTES: &[(&str, &[&str])] = &[("/get_face/retina", &["input"])];
+
+    fn multipart_body(fields: &[&str], filename: &str, content: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for field in fields {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                     Content-Type: application/octet-stream\r\n\r\n",
+                    BOUNDARY, field, filename
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let image = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 7 % 256) as u8, (y * 13 % 256) as u8, ((x + y) % 256) as u8])
+        });
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Jpeg)
+            .unwrap();
+        bytes
+    }
+
+    /// sends `content` to every face route, checks the status and the json error body
+    async fn expect_error(filename: &str, content: &[u8], status: StatusCode, code: &str) {
+        for extractor in [false, true] {
+            let app = test::init_service(face_app(extractor)).await;
+            let retina_routes = match extractor {
+                true => RETINA_ROUTES,
+                false => &[],
+            };
+            for (route, fields) in FACE_ROUTES.iter().chain(retina_routes) {
+                let request = test::TestRequest::post()
+                    .uri(route)
+                    .insert_header((
+                        header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={}", BOUNDARY),
+                    ))
+                    .set_payload(multipart_body(fields, filename, content))
+                    .to_request();
+                let response = test::call_service(&app, request).await;
+                assert_eq!(response.status(), status, "{} {}", route, filename);
+                assert_eq!(
+                    response.headers().get(header::CONTENT_TYPE).unwrap(),
+                    "application/json"
+                );
+                let body: serde_json::Value = test::read_body_json(response).await;
+                let body = body.as_object().unwrap();
+                assert_eq!(body.len(), 2, "{} {:?}", route, body);
+                assert_eq!(body["code"], code, "{} {:?}", route, body);
+                assert!(!body["message"].as_str().unwrap().is_empty());
+            }
+            // the worker is still answering after the failed requests
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[actix_web::test]
+    async fn truncated_jpeg_is_corrupt_image() {
+        let jpeg = jpeg(64, 64);
+        // cut in the headers, before any scan data
+        expect_error(
+            "face.jpg",
+            &jpeg[..100],
+            StatusCode::BAD_REQUEST,
+            "corrupt_image",
+        )
+        .await;
+        // cut in the middle of the scan
+        expect_error(
+            "face.jpg",
+            &jpeg[..jpeg.len() / 2],
+            StatusCode::BAD_REQUEST,
+            "corrupt_image",
+        )
+        .await;
+    }
+
+    #[actix_web::test]
+    async fn non_image_is_unsupported_format() {
+        expect_error(
+            "notes.txt",
+            b"this is a plain text file, not an image\n",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_format",
+        )
+        .await;
+    }
+
+    #[actix_web::test]
+    async fn upload_over_max_bytes_is_image_too_large() {
+        // under the form limit, rejected by the per image limit
+        let mut jpeg = jpeg(64, 64);
+        jpeg.resize(MAX_BYTES + 1, 0);
+        expect_error("face.jpg", &jpeg, StatusCode::PAYLOAD_TOO_LARGE, "image_too_large").await;
+    }
+
+    #[actix_web::test]
+    async fn upload_over_form_limit_is_image_too_large() {
+        let mut jpeg = jpeg(64, 64);
+        jpeg.resize(2 * MAX_BYTES + 128 * 1024, 0);
+        expect_error("face.jpg", &jpeg, StatusCode::PAYLOAD_TOO_LARGE, "image_too_large").await;
+    }
+}
@@ -1,12 +1,16 @@
-use soma_core::common_utils::{sort_conf_bbox, Bbox};
+use soma_core::common_utils::{sort_conf_bbox, Bbox, Detector};
 use crate::webserver::service;
 use actix_multipart::form::tempfile::TempFile;
 use actix_multipart::form::text::Text;
 use actix_multipart::form::MultipartForm;
 use actix_multipart::Multipart;
+use crate::webserver::error::FaceApiError;
+use crate::webserver::ingest::{FaceUploadForm, ImagePairRequest, ImageSource, VerifyTarget};
+use crate::pose::{estimate_head_pose, HeadPose};
+use crate::quality::QualityReport;
 use anyhow::{Error, Result};
 use serde::{Deserialize, Serialize};
-use utoipa::{OpenApi, ToSchema};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use utoipa_swagger_ui::{SwaggerUi, Url};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
@@ -24,6 +28,15 @@ fn get_wh(input_bbox: &Bbox) -> (i32, i32) {
 }
 
 impl FaceResponse {
+    pub fn from_bbox(input_bbox: &Bbox) -> FaceResponse {
+        let (w, h) = get_wh(input_bbox);
+        FaceResponse {
+            confidence: input_bbox.confidence,
+            width: w,
+            height: h,
+        }
+    }
+
     /// takes in a vec of bbox
     pub fn from_bbox_vec(input_vec: &Vec<Bbox>) -> Vec<FaceResponse> {
         // let mut faces: Vec<FaceResponse> = vec![];
@@ -45,25 +58,436 @@ impl FaceResponse {
     }
 }
 
+/// `version`: response schema version, 1 (default) returns [FaceResponse],
+/// 2 returns [FaceResponseV2]
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ResponseVersionQuery {
+    #[param(example = 2)]
+    pub version: Option<u8>,
+}
+
+impl ResponseVersionQuery {
+    pub fn is_v2(&self) -> bool {
+        self.version.unwrap_or(1) >= 2
+    }
+}
+
+/// box coordinates divided by the image width / height (0.0 - 1.0)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+pub struct NormalizedBox {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+}
+
+/// version 2 of [FaceResponse], coordinates are in original image pixels,
+/// `keypoints` are `[x, y]` (left eye, right eye, nose, left mouth, right mouth)
+/// and empty if the detector does not predict them, `pose` is estimated from them.
+/// `quality` is only set when the request asks for it (`quality=true`)
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FaceResponseV2 {
+    pub x1: f32,
+    pub y1: f32,
+    pub x2: f32,
+    pub y2: f32,
+    pub width: f32,
+    pub height: f32,
+    pub normalized: NormalizedBox,
+    #[schema(value_type = Vec<Vec<f32>>, example = "[[30.1, 70.4], [62.3, 71.0], [45.9, 90.2], [33.0, 108.7], [60.8, 109.1]]")]
+    pub keypoints: Vec<Vec<f32>>,
+    pub confidence: f32,
+    #[schema(value_type = String, example = "yolo")]
+    pub detector: Detector,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose: Option<HeadPose>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<QualityReport>,
+}
+
+impl FaceResponseV2 {
+    pub fn from_bbox(input_bbox: &Bbox, image_width: u32, image_height: u32) -> FaceResponseV2 {
+        let (img_w, img_h) = (image_width.max(1) as f32, image_height.max(1) as f32);
+        FaceResponseV2 {
+            x1: input_bbox.x1,
+            y1: input_bbox.y1,
+            x2: input_bbox.x2,
+            y2: input_bbox.y2,
+            width: input_bbox.x2 - input_bbox.x1,
+            height: input_bbox.y2 - input_bbox.y1,
+            normalized: NormalizedBox {
+                x1: input_bbox.x1 / img_w,
+                y1: input_bbox.y1 / img_h,
+                x2: input_bbox.x2 / img_w,
+                y2: input_bbox.y2 / img_h,
+            },
+            keypoints: input_bbox
+                .kpss
+                .iter()
+                .filter(|x| x.len() >= 2)
+                .map(|x| vec![x[0], x[1]])
+                .collect(),
+            confidence: input_bbox.confidence,
+            detector: input_bbox.detector,
+            pose: estimate_head_pose(&input_bbox.kpss),
+            quality: None,
+        }
+    }
+
+    pub fn with_quality(mut self, quality: Option<QualityReport>) -> FaceResponseV2 {
+        self.quality = quality;
+        self
+    }
+
+    /// takes in a vec of bbox
+    pub fn from_bbox_vec(
+        input_vec: &[Bbox],
+        image_width: u32,
+        image_height: u32,
+    ) -> Vec<FaceResponseV2> {
+        input_vec
+            .iter()
+            .map(|x| FaceResponseV2::from_bbox(x, image_width, image_height))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct GetFaceResponseV2 {
+    #[schema(example = 2)]
+    pub version: u8,
+    pub data: Vec<FaceResponseV2>,
+    #[schema(value_type = String, example = "success")]
+    pub message: String,
+}
+
+impl GetFaceResponseV2 {
+    pub fn new(data: Vec<FaceResponseV2>, message: String) -> GetFaceResponseV2 {
+        GetFaceResponseV2 {
+            version: 2,
+            data,
+            message,
+        }
+    }
+}
+
+/// which face detector a request runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectorChoice {
+    Yolo,
+    Retinaface,
+    /// both detectors merged with NMS, needs retinaface to be loaded
+    #[serde(alias = "both")]
+    All,
+}
+
+/// detection tuning shared by the face endpoints,
+/// unset fields fall back to the route defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectionParams {
+    pub confidence: f32,
+    /// NMS IoU threshold, `None` keeps the model default
+    pub iou: Option<f32>,
+    pub max_faces: Option<usize>,
+    /// minimum box side in original image pixels
+    pub min_face_size: f32,
+    /// `None` keeps the route default
+    pub detector: Option<DetectorChoice>,
+}
+
+impl DetectionParams {
+    /// route defaults, every field but `confidence` is unset
+    pub fn with_confidence(confidence: f32) -> DetectionParams {
+        DetectionParams {
+            confidence,
+            iou: None,
+            max_faces: None,
+            min_face_size: 0.0,
+            detector: None,
+        }
+    }
+
+    pub fn with_max_faces(mut self, max_faces: usize) -> DetectionParams {
+        self.max_faces = Some(max_faces);
+        self
+    }
+
+    /// overrides `self` with the request fields and validates them
+    pub fn merge(mut self, fields: &FaceRequestFields) -> Result<DetectionParams, String> {
+        if let Some(confidence) = fields.confidence {
+            if !(0.0..=1.0).contains(&confidence) {
+                return Err(format!("`confidence` must be between 0 and 1, got {}", confidence));
+            }
+            self.confidence = confidence;
+        }
+        if let Some(iou) = fields.iou {
+            if !(iou > 0.0 && iou <= 1.0) {
+                return Err(format!("`iou` must be in (0, 1], got {}", iou));
+            }
+            self.iou = Some(iou);
+        }
+        if let Some(max_faces) = fields.max_faces {
+            if max_faces == 0 {
+                return Err(String::from("`max_faces` must be at least 1"));
+            }
+            self.max_faces = Some(max_faces);
+        }
+        if let Some(min_face_size) = fields.min_face_size {
+            if !(min_face_size >= 0.0) {
+                return Err(format!("`min_face_size` must be positive, got {}", min_face_size));
+            }
+            self.min_face_size = min_face_size;
+        }
+        if let Some(detector) = fields.detector {
+            self.detector = Some(detector);
+        }
+        Ok(self)
+    }
+
+    /// drops faces under `min_face_size`, sorts by confidence and keeps `max_faces`
+    pub fn filter(&self, mut bboxes: Vec<Bbox>) -> Vec<Bbox> {
+        bboxes.retain(|x| (x.x2 - x.x1).min(x.y2 - x.y1) >= self.min_face_size);
+        let mut bboxes = sort_conf_bbox(&mut bboxes);
+        if let Some(max_faces) = self.max_faces {
+            bboxes.truncate(max_faces);
+        }
+        bboxes
+    }
+}
+
+/// optional fields of the face routes, whatever the request content type.
+/// json bodies carry them next to the image, raw `image/*` bodies in the query string
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct FaceRequestFields {
+    #[schema(example = false)]
+    pub aligned: Option<bool>,
+    #[schema(example = 0.5)]
+    pub confidence: Option<f32>,
+    #[schema(example = 0.4)]
+    pub iou: Option<f32>,
+    #[schema(example = 10)]
+    pub max_faces: Option<usize>,
+    #[schema(example = 32)]
+    pub min_face_size: Option<f32>,
+    pub detector: Option<DetectorChoice>,
+    /// adds a [QualityReport] to every face of a version 2 detection response
+    #[schema(example = false)]
+    pub quality: Option<bool>,
+}
+
+/// unwraps an optional multipart text field
+fn text<T: serde::de::DeserializeOwned>(field: Option<Text<T>>) -> Option<T> {
+    field.map(|x| x.0)
+}
+
+impl FaceRequestFields {
+    pub fn is_aligned(&self) -> bool {
+        self.aligned.unwrap_or(false)
+    }
+
+    pub fn wants_quality(&self) -> bool {
+        self.quality.unwrap_or(false)
+    }
+
+    /// for routes that always keep a single face
+    pub fn without_max_faces(&self) -> FaceRequestFields {
+        FaceRequestFields {
+            max_faces: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// `application/json` body of the face routes, exactly one of
+/// `image_base64` (optionally a `data:` url) or `url` must be set.
+/// the other fields are the same as the multipart form
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FaceJsonRequest {
+    #[schema(example = "iVBORw0KGgo...")]
+    pub image_base64: Option<String>,
+    /// only hosts in the server allowlist are fetched
+    #[schema(example = "https://images.example.com/face.png")]
+    pub url: Option<String>,
+    #[serde(flatten)]
+    pub fields: FaceRequestFields,
+}
+
+/// `aligned`: the input is already a cropped and aligned face,
+/// if false (the default) the largest face is detected, cropped and aligned first.
+/// the detection fields are the same as [GetFaceRequest] and only used when `aligned` is false
 #[derive(Debug, MultipartForm, ToSchema)]
 pub struct GetFaceVecRequest {
-    #[schema(value_type = String, example = "face.png")]
-    pub input: TempFile,
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub input: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/face.png", required=false)]
+    pub url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub image_base64: Option<Text<String>>,
 
     #[schema(value_type = bool, example = "true", required=false)]
-    pub aligned: Text<bool>,
+    pub aligned: Option<Text<bool>>,
+
+    #[schema(value_type = f32, example = "0.5", required=false)]
+    pub confidence: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "0.4", required=false)]
+    pub iou: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "32", required=false)]
+    pub min_face_size: Option<Text<f32>>,
+    #[schema(value_type = DetectorChoice, required=false)]
+    pub detector: Option<Text<DetectorChoice>>,
 }
 
+impl FaceUploadForm for GetFaceVecRequest {
+    fn into_request(self) -> Result<(ImageSource, FaceRequestFields), FaceApiError> {
+        let image = ImageSource::pick(self.input, text(self.image_base64), text(self.url))?;
+        let fields = FaceRequestFields {
+            aligned: text(self.aligned),
+            confidence: text(self.confidence),
+            iou: text(self.iou),
+            max_faces: None,
+            min_face_size: text(self.min_face_size),
+            detector: text(self.detector),
+            quality: None,
+        };
+        Ok((image, fields))
+    }
+}
+
+/// `bbox` is the detection the embedding was taken from,
+/// only present when the face was detected by the server (`aligned` = false)
 #[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct GetFaceVecResponse {
     #[schema(value_type = Vec<f32>, example = "[-0.231,...,-0.42]")]
     pub data: Vec<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<FaceResponseV2>,
+}
+
+/// `confidence`: minimum detection confidence for a face to be embedded, defaults to 0.5,
+/// the other detection fields are the same as [GetFaceRequest]
+#[derive(Debug, MultipartForm, ToSchema)]
+pub struct GetFaceVecsRequest {
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub input: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/group.png", required=false)]
+    pub url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub image_base64: Option<Text<String>>,
+
+    #[schema(value_type = f32, example = "0.5", required=false)]
+    pub confidence: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "0.4", required=false)]
+    pub iou: Option<Text<f32>>,
+    #[schema(value_type = usize, example = "10", required=false)]
+    pub max_faces: Option<Text<usize>>,
+    #[schema(value_type = f32, example = "32", required=false)]
+    pub min_face_size: Option<Text<f32>>,
+    #[schema(value_type = DetectorChoice, required=false)]
+    pub detector: Option<Text<DetectorChoice>>,
+}
+
+impl FaceUploadForm for GetFaceVecsRequest {
+    fn into_request(self) -> Result<(ImageSource, FaceRequestFields), FaceApiError> {
+        let image = ImageSource::pick(self.input, text(self.image_base64), text(self.url))?;
+        let fields = FaceRequestFields {
+            aligned: None,
+            confidence: text(self.confidence),
+            iou: text(self.iou),
+            max_faces: text(self.max_faces),
+            min_face_size: text(self.min_face_size),
+            detector: text(self.detector),
+            quality: None,
+        };
+        Ok((image, fields))
+    }
+}
+
+/// one detected face and its embedding,
+/// `bbox` is `[x1, y1, x2, y2]` in original image pixels,
+/// `pose` is missing if the detector has no keypoints
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FaceEmbeddingResponse {
+    #[schema(value_type = Vec<f32>, example = "[12.0, 40.5, 88.2, 130.9]")]
+    pub bbox: [f32; 4],
+    #[schema(value_type = Vec<Vec<f32>>, example = "[[30.1, 70.4], [62.3, 71.0], [45.9, 90.2], [33.0, 108.7], [60.8, 109.1]]")]
+    pub keypoints: Vec<Vec<f32>>,
+    pub confidence: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pose: Option<HeadPose>,
+    #[schema(value_type = Vec<f32>, example = "[-0.231,...,-0.42]")]
+    pub embedding: Vec<f32>,
+}
+
+impl FaceEmbeddingResponse {
+    pub fn new(bbox: &Bbox, embedding: Vec<f32>) -> FaceEmbeddingResponse {
+        FaceEmbeddingResponse {
+            bbox: [bbox.x1, bbox.y1, bbox.x2, bbox.y2],
+            keypoints: bbox
+                .kpss
+                .iter()
+                .filter(|x| !x.is_empty())
+                .cloned()
+                .collect(),
+            confidence: bbox.confidence,
+            pose: estimate_head_pose(&bbox.kpss),
+            embedding,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct GetFaceVecsResponse {
+    pub data: Vec<FaceEmbeddingResponse>,
+    #[schema(value_type = String, example = "success")]
+    pub message: String,
 }
 
+/// every detection field is optional:
+/// `confidence`: minimum detection confidence (0 - 1),
+/// `iou`: NMS IoU threshold (0 - 1], defaults to the model threshold,
+/// `max_faces`: keep only the most confident faces,
+/// `min_face_size`: drop boxes whose shortest side is smaller (pixels),
+/// `detector`: `yolo`, `retinaface` or `all` (retinaface and all need retinaface loaded),
+/// `quality`: score every face, only returned with `?version=2`
 #[derive(Debug, MultipartForm, ToSchema)]
 pub struct GetFaceRequest {
-    #[schema(value_type = String, example = "face.png")]
-    pub input: TempFile,
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub input: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/face.png", required=false)]
+    pub url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub image_base64: Option<Text<String>>,
+
+    #[schema(value_type = f32, example = "0.1", required=false)]
+    pub confidence: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "0.4", required=false)]
+    pub iou: Option<Text<f32>>,
+    #[schema(value_type = usize, example = "10", required=false)]
+    pub max_faces: Option<Text<usize>>,
+    #[schema(value_type = f32, example = "32", required=false)]
+    pub min_face_size: Option<Text<f32>>,
+    #[schema(value_type = DetectorChoice, required=false)]
+    pub detector: Option<Text<DetectorChoice>>,
+    #[schema(value_type = bool, example = "false", required=false)]
+    pub quality: Option<Text<bool>>,
+}
+
+impl FaceUploadForm for GetFaceRequest {
+    fn into_request(self) -> Result<(ImageSource, FaceRequestFields), FaceApiError> {
+        let image = ImageSource::pick(self.input, text(self.image_base64), text(self.url))?;
+        let fields = FaceRequestFields {
+            aligned: None,
+            confidence: text(self.confidence),
+            iou: text(self.iou),
+            max_faces: text(self.max_faces),
+            min_face_size: text(self.min_face_size),
+            detector: text(self.detector),
+            quality: text(self.quality),
+        };
+        Ok((image, fields))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -74,16 +498,56 @@ pub struct GetFaceResponse {
     pub message: String,
 }
 
+/// body of every error response, see [crate::webserver::error::FaceApiError]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ErrorResponse {
+    /// stable, machine readable error code
+    #[schema(value_type = String, example = "unsupported_format")]
+    pub code: String,
+    #[schema(value_type = String, example = "the uploaded file is not a supported image format")]
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct GetFaceResponseNone {
     #[schema(value_type = String, example = "no detections were found, please try with a better image")]
     pub message: String,
 }
 
-#[derive(MultipartForm, Debug, ToSchema)]
+/// detection fields are the same as [GetFaceRequest], `max_faces` does not apply
+#[derive(Debug, MultipartForm, ToSchema)]
 pub struct GetLargestFaceRequest {
-    #[schema(value_type = String, example = "face.png")]
-    pub input: TempFile,
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub input: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/face.png", required=false)]
+    pub url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub image_base64: Option<Text<String>>,
+
+    #[schema(value_type = f32, example = "0.5", required=false)]
+    pub confidence: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "0.4", required=false)]
+    pub iou: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "32", required=false)]
+    pub min_face_size: Option<Text<f32>>,
+    #[schema(value_type = DetectorChoice, required=false)]
+    pub detector: Option<Text<DetectorChoice>>,
+}
+
+impl FaceUploadForm for GetLargestFaceRequest {
+    fn into_request(self) -> Result<(ImageSource, FaceRequestFields), FaceApiError> {
+        let image = ImageSource::pick(self.input, text(self.image_base64), text(self.url))?;
+        let fields = FaceRequestFields {
+            aligned: None,
+            confidence: text(self.confidence),
+            iou: text(self.iou),
+            max_faces: None,
+            min_face_size: text(self.min_face_size),
+            detector: text(self.detector),
+            quality: None,
+        };
+        Ok((image, fields))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
@@ -100,3 +564,143 @@ impl GetLargestFaceResponse {
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct GetLargestFaceResponseV2 {
+    #[schema(example = 2)]
+    pub version: u8,
+    pub coords: FaceResponseV2,
+    pub cropped_face: String,
+}
+
+impl GetLargestFaceResponseV2 {
+    pub fn new(coords: FaceResponseV2, cropped_face: String) -> GetLargestFaceResponseV2 {
+        GetLargestFaceResponseV2 {
+            version: 2,
+            coords,
+            cropped_face,
+        }
+    }
+}
+
+/// quality of the largest face, `passed` is true if it clears every configured threshold
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct FaceQualityResponse {
+    pub bbox: FaceResponseV2,
+    pub quality: QualityReport,
+    #[schema(value_type = String, example = "success")]
+    pub message: String,
+}
+
+/// `/verify` multipart form, each side is a file (`first` / `second`),
+/// a `*_base64` image or a `*_url`. `aligned` and the detection fields apply to both images,
+/// `threshold` overrides the server match threshold
+#[derive(Debug, MultipartForm, ToSchema)]
+pub struct VerifyRequest {
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub first: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/a.png", required=false)]
+    pub first_url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub first_base64: Option<Text<String>>,
+
+    #[schema(value_type = String, format = Binary, required=false)]
+    pub second: Option<TempFile>,
+    #[schema(value_type = String, example = "https://images.example.com/b.png", required=false)]
+    pub second_url: Option<Text<String>>,
+    #[schema(value_type = String, required=false)]
+    pub second_base64: Option<Text<String>>,
+
+    #[schema(value_type = f32, example = "0.35", required=false)]
+    pub threshold: Option<Text<f32>>,
+    #[schema(value_type = bool, example = "false", required=false)]
+    pub aligned: Option<Text<bool>>,
+    #[schema(value_type = f32, example = "0.5", required=false)]
+    pub confidence: Option<Text<f32>>,
+    #[schema(value_type = f32, example = "32", required=false)]
+    pub min_face_size: Option<Text<f32>>,
+    #[schema(value_type = DetectorChoice, required=false)]
+    pub detector: Option<Text<DetectorChoice>>,
+}
+
+impl VerifyRequest {
+    pub fn into_pair(self) -> Result<ImagePairRequest, FaceApiError> {
+        let first = ImageSource::pick(self.first, text(self.first_base64), text(self.first_url))?;
+        let second =
+            ImageSource::pick(self.second, text(self.second_base64), text(self.second_url))?;
+        let fields = FaceRequestFields {
+            aligned: text(self.aligned),
+            confidence: text(self.confidence),
+            iou: None,
+            max_faces: None,
+            min_face_size: text(self.min_face_size),
+            detector: text(self.detector),
+            quality: None,
+        };
+        Ok(ImagePairRequest {
+            first,
+            second: VerifyTarget::Image(second),
+            fields,
+            threshold: text(self.threshold),
+        })
+    }
+}
+
+/// `application/json` body of `/verify`, each side takes `*_base64` or `*_url`.
+/// `second_embedding` replaces the second image with a vector computed earlier
+/// (the gateway sends the stored vector of a `face_uuid` this way)
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyJsonRequest {
+    pub first_base64: Option<String>,
+    #[schema(example = "https://images.example.com/a.png")]
+    pub first_url: Option<String>,
+    pub second_base64: Option<String>,
+    #[schema(example = "https://images.example.com/b.png")]
+    pub second_url: Option<String>,
+    #[schema(value_type = Vec<f32>, example = "[-0.231,...,-0.42]")]
+    pub second_embedding: Option<Vec<f32>>,
+    #[schema(example = 0.35)]
+    pub threshold: Option<f32>,
+    #[serde(flatten)]
+    pub fields: FaceRequestFields,
+}
+
+impl VerifyJsonRequest {
+    pub fn into_pair(self) -> Result<ImagePairRequest, FaceApiError> {
+        let first = ImageSource::pick(None, self.first_base64, self.first_url)?;
+        let second = match (self.second_embedding, self.second_base64, self.second_url) {
+            (Some(embedding), None, None) => VerifyTarget::Embedding(embedding),
+            (Some(_), _, _) => {
+                return Err(FaceApiError::InvalidRequest(String::from(
+                    "send only one of `second_embedding`, `second_base64` or `second_url`",
+                )))
+            }
+            (None, base64, url) => VerifyTarget::Image(ImageSource::pick(None, base64, url)?),
+        };
+        Ok(ImagePairRequest {
+            first,
+            second,
+            fields: self.fields.without_max_faces(),
+            threshold: self.threshold,
+        })
+    }
+}
+
+/// `similarity` is the cosine similarity of the two face vectors,
+/// `is_match` is true when it reaches `threshold`.
+/// `first` / `second` are the faces that were compared, only present when the
+/// server detected them (`aligned` = false, `second` is missing for `second_embedding`)
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyResponse {
+    #[schema(example = 0.71)]
+    pub similarity: f32,
+    #[schema(example = 0.35)]
+    pub threshold: f32,
+    pub is_match: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first: Option<FaceResponseV2>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second: Option<FaceResponseV2>,
+    #[schema(value_type = String, example = "success")]
+    pub message: String,
+}
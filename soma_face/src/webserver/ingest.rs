@@ -0,0 +1,259 @@
+//! request side of image ingestion for the face routes.
+//!
+//! [ImageRequest] accepts the route's multipart form, a json body with
+//! `image_base64` or `url`, or a raw `image/*` body with the other fields in the
+//! query string. [ImagePairRequest] does the same for the two images of
+//! `/verify` (multipart or json only). url images are fetched here (async,
+//! it's io), everything is decoded by [soma_core::image_ingest] on the blocking pool
+
+use crate::webserver::common_utils::tempfile_to_dynimg;
+use crate::webserver::error::FaceApiError;
+use crate::webserver::handler::{
+    FaceJsonRequest, FaceRequestFields, VerifyJsonRequest, VerifyRequest,
+};
+use actix_multipart::form::tempfile::TempFile;
+use actix_multipart::form::{MultipartCollect, MultipartForm};
+use actix_web::dev::Payload;
+use actix_web::web::{self, Bytes};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use image::DynamicImage;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use soma_core::image_ingest::{decode_base64, decode_image, IngestConfig, IngestError};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// where the image of a request comes from
+pub enum ImageSource {
+    File(TempFile),
+    Bytes(Bytes),
+    Base64(String),
+    Url(String),
+}
+
+impl ImageSource {
+    /// exactly one of the inputs must be set
+    pub fn pick(
+        file: Option<TempFile>,
+        base64: Option<String>,
+        url: Option<String>,
+    ) -> Result<ImageSource, FaceApiError> {
+        match (file, base64, url) {
+            (Some(file), None, None) => Ok(ImageSource::File(file)),
+            (None, Some(base64), None) => Ok(ImageSource::Base64(base64)),
+            (None, None, Some(url)) => Ok(ImageSource::Url(url)),
+            (None, None, None) => Err(FaceApiError::InvalidRequest(String::from(
+                "no image given, send `input`, `image_base64` or `url`",
+            ))),
+            _ => Err(FaceApiError::InvalidRequest(String::from(
+                "send only one of `input`, `image_base64` or `url`",
+            ))),
+        }
+    }
+}
+
+/// multipart form of a face route
+pub trait FaceUploadForm: MultipartCollect + Sized + 'static {
+    fn into_request(self) -> Result<(ImageSource, FaceRequestFields), FaceApiError>;
+}
+
+/// image and optional fields of a face route, from any supported content type.
+/// `F` is the route's multipart form
+pub struct ImageRequest<F> {
+    pub image: ImageSource,
+    pub fields: FaceRequestFields,
+    form: PhantomData<F>,
+}
+
+impl<F: FaceUploadForm> FromRequest for ImageRequest<F> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let mut payload = payload.take();
+        Box::pin(async move {
+            let content_type = req.content_type().to_lowercase();
+            let (image, fields) = if content_type.starts_with("multipart/form-data") {
+                MultipartForm::<F>::from_request(&req, &mut payload)
+                    .await?
+                    .into_inner()
+                    .into_request()?
+            } else if content_type.starts_with("application/json") {
+                let json = web::Json::<FaceJsonRequest>::from_request(&req, &mut payload)
+                    .await?
+                    .into_inner();
+                let image = ImageSource::pick(None, json.image_base64, json.url)?;
+                (image, json.fields)
+            } else if content_type.starts_with("image/")
+                || content_type.starts_with("application/octet-stream")
+            {
+                let fields = web::Query::<FaceRequestFields>::from_query(req.query_string())
+                    .map_err(|e| FaceApiError::InvalidRequest(e.to_string()))?
+                    .into_inner();
+                let bytes = Bytes::from_request(&req, &mut payload).await?;
+                (ImageSource::Bytes(bytes), fields)
+            } else {
+                return Err(FaceApiError::UnsupportedFormat(format!(
+                    "content type `{}`, send multipart/form-data, application/json or image/*",
+                    content_type
+                ))
+                .into());
+            };
+            Ok(ImageRequest {
+                image,
+                fields,
+                form: PhantomData,
+            })
+        })
+    }
+}
+
+/// second side of `/verify`
+pub enum VerifyTarget {
+    Image(ImageSource),
+    /// a vector computed earlier, e.g. the stored vector of a `face_uuid`
+    Embedding(Vec<f32>),
+}
+
+/// the two images (or image and vector) of `/verify`, from a multipart form or a json body
+pub struct ImagePairRequest {
+    pub first: ImageSource,
+    pub second: VerifyTarget,
+    pub fields: FaceRequestFields,
+    /// overrides the server match threshold
+    pub threshold: Option<f32>,
+}
+
+impl FromRequest for ImagePairRequest {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let mut payload = payload.take();
+        Box::pin(async move {
+            let content_type = req.content_type().to_lowercase();
+            let request = if content_type.starts_with("multipart/form-data") {
+                MultipartForm::<VerifyRequest>::from_request(&req, &mut payload)
+                    .await?
+                    .into_inner()
+                    .into_pair()?
+            } else if content_type.starts_with("application/json") {
+                web::Json::<VerifyJsonRequest>::from_request(&req, &mut payload)
+                    .await?
+                    .into_inner()
+                    .into_pair()?
+            } else {
+                return Err(FaceApiError::UnsupportedFormat(format!(
+                    "content type `{}`, send multipart/form-data or application/json",
+                    content_type
+                ))
+                .into());
+            };
+            Ok(request)
+        })
+    }
+}
+
+/// ingest limits plus the client url images are fetched with,
+/// one per worker (the client's connection pool belongs to the worker runtime)
+pub struct ImageIngest {
+    config: IngestConfig,
+    client: reqwest::Client,
+}
+
+impl ImageIngest {
+    pub fn new(config: IngestConfig) -> Result<ImageIngest, reqwest::Error> {
+        // redirects are only followed to allowlisted hosts
+        let redirect_config = config.clone();
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.url_timeout_secs))
+            .redirect(Policy::custom(move |attempt| {
+                match attempt.previous().len() < 5 && is_allowed(&redirect_config, attempt.url()) {
+                    true => attempt.follow(),
+                    false => attempt.stop(),
+                }
+            }))
+            .build()?;
+        Ok(ImageIngest { config, client })
+    }
+
+    pub fn config(&self) -> &IngestConfig {
+        &self.config
+    }
+
+    /// downloads url images, other sources are passed through untouched
+    pub async fn fetch(&self, source: ImageSource) -> Result<PendingImage, FaceApiError> {
+        let source = match source {
+            ImageSource::Url(url) => ImageSource::Bytes(self.download(&url).await?),
+            source => source,
+        };
+        Ok(PendingImage {
+            source,
+            config: self.config.clone(),
+        })
+    }
+
+    async fn download(&self, url: &str) -> Result<Bytes, IngestError> {
+        let parsed = Url::parse(url).map_err(|_| IngestError::UrlNotAllowed(url.to_string()))?;
+        if !is_allowed(&self.config, &parsed) {
+            return Err(IngestError::UrlNotAllowed(url.to_string()));
+        }
+        let mut response = self
+            .client
+            .get(parsed)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|e| IngestError::Fetch(e.to_string()))?;
+        let too_large = IngestError::TooLarge {
+            limit: self.config.max_bytes,
+        };
+        if response.content_length().unwrap_or(0) > self.config.max_bytes as u64 {
+            return Err(too_large);
+        }
+        // the content length may be missing or wrong, count while reading
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| IngestError::Fetch(e.to_string()))?
+        {
+            if body.len() + chunk.len() > self.config.max_bytes {
+                return Err(too_large);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Bytes::from(body))
+    }
+}
+
+/// only http(s) urls to allowlisted hosts
+fn is_allowed(config: &IngestConfig, url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some_and(|host| config.allows_url_host(host))
+}
+
+/// a fetched image waiting to be decoded on the blocking pool
+pub struct PendingImage {
+    source: ImageSource,
+    config: IngestConfig,
+}
+
+impl PendingImage {
+    /// decodes the image, blocks
+    pub fn decode(self) -> Result<DynamicImage, FaceApiError> {
+        let image = match self.source {
+            ImageSource::File(file) => tempfile_to_dynimg(file, &self.config)?,
+            ImageSource::Bytes(bytes) => decode_image(&bytes, &self.config)?,
+            ImageSource::Base64(data) => decode_base64(&data, &self.config)?,
+            ImageSource::Url(url) => {
+                return Err(FaceApiError::Internal(format!("`{}` was not fetched", url)))
+            }
+        };
+        Ok(image)
+    }
+}
@@ -1,6 +1,9 @@
 //! web server functionalities
 
+pub mod blocking;
 pub mod common_utils;
 pub mod documentation;
+pub mod error;
 pub mod handler;
+pub mod ingest;
 pub mod service;
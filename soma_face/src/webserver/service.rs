@@ -1,19 +1,27 @@
 //! face services compilation
 
-use soma_core::common_utils::{get_largest_bbox,sort_conf_bbox};
+use soma_core::common_utils::{get_largest_bbox, non_maximum_suppression, sort_conf_bbox};
 use soma_core::common_utils::Bbox;
-use soma_core::onnx_backend::{Inference, InferenceResult};
-use crate::get_face::retinaface::GetFaceRetinaface;
+use crate::get_face::retinaface::SCRFD_NMS_THRESHOLD;
 use crate::get_face::yolo::GetFaceYolo;
 use crate::get_face::FaceExtractor;
 use crate::get_face_vec::arcface::GetFaceVecArcFace;
-use crate::webserver::common_utils::{tempfile_to_dynimg, dynimg_to_bytes, image_to_base64};
+use crate::get_face_vec::cosine_similarity;
+use crate::config::VerifyConfig;
+use crate::webserver::common_utils::{dynimg_to_bytes, image_to_base64};
 use crate::webserver::handler::FaceResponse;
 use crate::webserver::handler::{
-    GetFaceRequest, GetFaceResponse, GetFaceResponseNone, GetLargestFaceResponse, GetLargestFaceRequest, GetFaceVecRequest,GetFaceVecResponse,
+    DetectionParams, DetectorChoice, FaceEmbeddingResponse, FaceQualityResponse, FaceResponseV2, ErrorResponse, GetFaceRequest, GetFaceResponse, GetFaceResponseNone, GetFaceResponseV2, GetLargestFaceResponse, GetLargestFaceResponseV2, GetLargestFaceRequest, GetFaceVecRequest,GetFaceVecResponse,
+    GetFaceVecsRequest, GetFaceVecsResponse, ResponseVersionQuery, VerifyRequest, VerifyResponse,
 };
+use crate::align::align_face;
+use crate::batching::{DetectionJob, DetectionQueue, EmbeddingQueue};
+use crate::quality::{assess_face, QualityConfig, QualityReport};
+use crate::webserver::blocking::BlockingPool;
+use crate::webserver::error::FaceApiError;
+use crate::webserver::ingest::{ImageIngest, ImagePairRequest, ImageRequest, VerifyTarget};
+use anyhow::Error;
 use image::{DynamicImage};
-use actix_multipart::form::MultipartForm;
 use actix_web::http::header::ContentType;
 use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use utoipa::OpenApi;
@@ -27,185 +35,681 @@ pub async fn index(req: HttpRequest) -> HttpResponse {
         .body("server is up :)")
 }
 
+/// picks the detector for `params`, by default retinaface + yolo when both are loaded
+/// and yolo otherwise. fails if the requested detector is not loaded
+fn resolve_detector(
+    yolo_model: &Option<web::Data<GetFaceYolo>>,
+    face_extractor: &Option<web::Data<FaceExtractor>>,
+    params: &DetectionParams,
+) -> Result<DetectorChoice, FaceApiError> {
+    let choice = params.detector.unwrap_or(match face_extractor {
+        Some(_) => DetectorChoice::All,
+        None => DetectorChoice::Yolo,
+    });
+    match (choice, yolo_model, face_extractor) {
+        (DetectorChoice::Yolo, None, None) => Err(FaceApiError::Internal(String::from(
+            "no face detector loaded",
+        ))),
+        (DetectorChoice::Yolo, _, _) | (_, _, Some(_)) => Ok(choice),
+        _ => Err(FaceApiError::ModelUnavailable(String::from(
+            "retinaface is not loaded on this server, use `detector=yolo`",
+        ))),
+    }
+}
+
+/// runs the detector picked by [resolve_detector] and applies the size and count filters.
+/// returned faces are sorted by confidence. blocks, meant to run on the [BlockingPool]
+fn detect_with_params(
+    choice: DetectorChoice,
+    yolo_model: &Option<web::Data<GetFaceYolo>>,
+    face_extractor: &Option<web::Data<FaceExtractor>>,
+    yolo_queue: &Option<web::Data<DetectionQueue>>,
+    input_image: &DynamicImage,
+    params: &DetectionParams,
+) -> Result<Vec<Bbox>, FaceApiError> {
+    let yolo = match (yolo_model, face_extractor) {
+        (Some(yolo), _) => Some(yolo.get_ref()),
+        (None, Some(extractor)) => Some(extractor.yolo_model.as_ref()),
+        (None, None) => None,
+    };
+    let bboxes = match (choice, yolo, face_extractor) {
+        (DetectorChoice::Yolo, Some(yolo), _) => run_yolo(
+            yolo,
+            yolo_queue,
+            input_image,
+            params.confidence,
+            params.iou.unwrap_or(yolo.nms_threshold),
+        )?,
+        (DetectorChoice::Retinaface, _, Some(extractor)) => extractor.retina_model.detect(
+            input_image,
+            params.confidence,
+            params.iou.unwrap_or(extractor.retina_model.nms_threshold),
+        )?,
+        // same merge as `FaceExtractor::extract_faces_from_image`, with yolo going through the queue
+        (DetectorChoice::All, Some(yolo), Some(extractor)) => {
+            let mut combined = run_yolo(
+                yolo,
+                yolo_queue,
+                input_image,
+                params.confidence,
+                yolo.nms_threshold,
+            )?;
+            combined.extend(extractor.retina_model.detect(
+                input_image,
+                params.confidence,
+                extractor.retina_model.nms_threshold,
+            )?);
+            non_maximum_suppression(combined, params.iou.unwrap_or(SCRFD_NMS_THRESHOLD))
+        }
+        _ => {
+            return Err(FaceApiError::ModelUnavailable(format!(
+                "face detector {:?} is not loaded",
+                choice
+            )))
+        }
+    };
+    Ok(params.filter(bboxes))
+}
+
+/// yolo through the batch queue if there is one
+fn run_yolo(
+    yolo: &GetFaceYolo,
+    yolo_queue: &Option<web::Data<DetectionQueue>>,
+    input_image: &DynamicImage,
+    confidence: f32,
+    iou: f32,
+) -> Result<Vec<Bbox>, Error> {
+    match yolo_queue {
+        Some(queue) => queue.submit(DetectionJob {
+            image: input_image.clone(),
+            confidence,
+            iou,
+        }),
+        None => yolo.detect(input_image, confidence, iou),
+    }
+}
+
+/// arcface through the batch queue if there is one, one vector per face
+fn embed_faces(
+    arcface: &GetFaceVecArcFace,
+    arcface_queue: &Option<web::Data<EmbeddingQueue>>,
+    faces: Vec<DynamicImage>,
+) -> Result<Vec<Vec<f32>>, Error> {
+    match arcface_queue {
+        Some(queue) => queue.submit_many(faces),
+        None => arcface.forward_batch(&faces),
+    }
+}
+
+/// aligns the face on its keypoints,
+/// detectors without keypoints fall back to a plain crop
+fn align_or_crop(input_image: &DynamicImage, bbox: &Bbox) -> Result<DynamicImage, FaceApiError> {
+    match align_face(input_image, &bbox.kpss) {
+        Ok(aligned) => Ok(aligned),
+        Err(_) => Ok(bbox.crop_bbox(input_image)?),
+    }
+}
+
+/// one [QualityReport] per face when the request asked for them
+fn assess_faces(
+    input_image: &DynamicImage,
+    bboxes: &[Bbox],
+    config: &QualityConfig,
+    enabled: bool,
+) -> Result<Option<Vec<QualityReport>>, FaceApiError> {
+    if !enabled {
+        return Ok(None);
+    }
+    let reports = bboxes
+        .iter()
+        .map(|bbox| assess_face(input_image, bbox, config))
+        .collect::<Result<Vec<QualityReport>, Error>>()?;
+    Ok(Some(reports))
+}
+
+/// detects the largest face and aligns it for embedding,
+/// [FaceApiError::NoFace] if there is none. blocks
+fn largest_aligned_face(
+    choice: DetectorChoice,
+    yolo_model: &Option<web::Data<GetFaceYolo>>,
+    face_extractor: &Option<web::Data<FaceExtractor>>,
+    yolo_queue: &Option<web::Data<DetectionQueue>>,
+    input_image: &DynamicImage,
+    params: &DetectionParams,
+) -> Result<(DynamicImage, FaceResponseV2), FaceApiError> {
+    let bboxes = detect_with_params(
+        choice,
+        yolo_model,
+        face_extractor,
+        yolo_queue,
+        input_image,
+        params,
+    )?;
+    if bboxes.is_empty() {
+        return Err(FaceApiError::NoFace);
+    }
+    let largest = get_largest_bbox(bboxes);
+    let face = align_or_crop(input_image, &largest)?;
+    let bbox = FaceResponseV2::from_bbox(&largest, input_image.width(), input_image.height());
+    Ok((face, bbox))
+}
+
+/// detection response in the requested schema version,
+/// `bboxes` should already be sorted. `qualities` are only sent in version 2
+fn face_detection_response(
+    bboxes: &[Bbox],
+    qualities: Option<Vec<QualityReport>>,
+    input_image: &DynamicImage,
+    version: &ResponseVersionQuery,
+) -> HttpResponse {
+    if bboxes.is_empty() {
+        return HttpResponse::Ok().json(GetFaceResponseNone {
+            message: String::from("no detections were found, please try with a better image!"),
+        });
+    }
+    match version.is_v2() {
+        true => {
+            let mut qualities = qualities.into_iter().flatten();
+            let (width, height) = (input_image.width(), input_image.height());
+            let data = FaceResponseV2::from_bbox_vec(bboxes, width, height)
+                .into_iter()
+                .map(|face| face.with_quality(qualities.next()))
+                .collect();
+            HttpResponse::Ok().json(GetFaceResponseV2::new(data, String::from("success")))
+        }
+        false => HttpResponse::Ok().json(GetFaceResponse {
+            data: FaceResponse::from_bbox_vec(&bboxes.to_vec()),
+            message: String::from("success"),
+        }),
+    }
+}
+
 /// get face vector with `ArcFace`
 /// this one always returns something
 /// regardless of accuracy lmao.
-/// please "align" (use cropped faces) for the best results
+/// if `aligned` is false the largest face is detected and aligned
+/// on its five keypoints before embedding
 #[utoipa::path(
     context_path="",
-    request_body(content = GetFaceVecRequest, content_type="multipart/form-data"),
+    request_body(content = GetFaceVecRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
     responses(
-        (status=200, description="returns face vectors (latent) using `arcface` model, returns results regardless of if face is aligned or not so please use a aligned (cropped) face", body=GetFaceVecResponse),
+        (status=200, description="returns face vectors (latent) using `arcface` model, if `aligned` is false the largest face is detected and aligned to the arcface reference landmarks first and returned as `bbox`", body=GetFaceVecResponse),
+        (status=400, description="`aligned` is false and no face was detected (`no_face`), the image is corrupt or the detection parameters are invalid", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
     )
 )]
 #[post("/get_vec")]
 pub async fn get_face_vectors(
     loaded_model: web::Data<GetFaceVecArcFace>,
-    form: MultipartForm<GetFaceVecRequest>,
+    yolo_model: Option<web::Data<GetFaceYolo>>,
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    arcface_queue: Option<web::Data<EmbeddingQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    request: ImageRequest<GetFaceVecRequest>,
     req: HttpRequest,
-) -> actix_web::Result<HttpResponse> {
-    let get_face_vec_req = form.into_inner();
-    let temp_file = get_face_vec_req.input;
-    // let mut file = temp_file.file;
-    let img = tempfile_to_dynimg(temp_file)?;
-    let t1 = std::time::Instant::now();
-    let face_vec = loaded_model.forward(&img, 0.0).unwrap();
-    println!("inference time {:?}", t1.elapsed());
-    // confidence field is literally not used  ^
-    let results = match face_vec {
-        InferenceResult::FaceEmbedding(real) => real,
-        _ => unreachable!(),
-    };
-    Ok(HttpResponse::Ok().json(GetFaceVecResponse { data: results }))
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.5)
+        .merge(&request.fields.without_max_faces())
+        .map_err(FaceApiError::InvalidRequest)?;
+    let is_aligned = request.fields.is_aligned();
+    let choice = resolve_detector(&yolo_model, &face_extractor, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    let response = pool
+        .run(move || {
+            let img = image.decode()?;
+            let t1 = std::time::Instant::now();
+            let (face, bbox) = match is_aligned {
+                true => (img, None),
+                false => {
+                    let (face, bbox) = largest_aligned_face(
+                        choice,
+                        &yolo_model,
+                        &face_extractor,
+                        &yolo_queue,
+                        &img,
+                        &params,
+                    )?;
+                    (face, Some(bbox))
+                }
+            };
+            let face_vec = embed_faces(&loaded_model, &arcface_queue, vec![face])?;
+            println!("inference time {:?}", t1.elapsed());
+            let results = face_vec
+                .into_iter()
+                .next()
+                .ok_or_else(|| FaceApiError::Internal(String::from("arcface returned no vector")))?;
+            Ok(GetFaceVecResponse {
+                data: results,
+                bbox,
+            })
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
 }
 
 #[utoipa::path(
     context_path="",
-    request_body(content = GetFaceRequest, content_type="multipart/form-data"),
+    params(ResponseVersionQuery),
+    request_body(content = GetFaceRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
     responses(
-        (status=200, description="returns face detections using `yolov8` model", body=GetFaceResponse),
+        (status=200, description="returns face detections using `yolov8` model, `?version=2` returns `GetFaceResponseV2`", body=GetFaceResponse),
+        (status=400, description="the image is corrupt, the detection parameters are invalid or the requested detector is not loaded", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
     )
 )]
 #[post("/get_face")]
 pub async fn get_face_bbox_yolo(
     loaded_model: web::Data<GetFaceYolo>,
-    form: MultipartForm<GetFaceRequest>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    quality_config: web::Data<QualityConfig>,
+    request: ImageRequest<GetFaceRequest>,
+    version: web::Query<ResponseVersionQuery>,
     req: HttpRequest,
-) -> actix_web::Result<HttpResponse> {
-    let get_face_req = form.into_inner();
-    let temp_file = get_face_req.input;
-    let img = tempfile_to_dynimg(temp_file)?;
-    let t1 = std::time::Instant::now();
-    let bboxes = loaded_model.forward(&img, 0.1).unwrap();
-    println!("inference time {:?}", t1.elapsed());
-    let mut _res = match bboxes {
-        InferenceResult::FaceDetection(ayylmao) => ayylmao,
-        _ => unreachable!(),
-    };
-    if _res.len() > 0 {
-        let _a = sort_conf_bbox(&mut _res);
-        Ok(HttpResponse::Ok().json(GetFaceResponse {
-            data: FaceResponse::from_bbox_vec(&_a),
-            message: String::from("success"),
-        }))
-    } else {
-        Ok(HttpResponse::Ok().json(GetFaceResponseNone {
-            message: String::from("no detections were found, please try with a better image!"),
-        }))
-    }
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.1)
+        .merge(&request.fields)
+        .map_err(FaceApiError::InvalidRequest)?;
+    let with_quality = request.fields.wants_quality() && version.is_v2();
+    let yolo_model = Some(loaded_model);
+    let choice = resolve_detector(&yolo_model, &None, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    let (img, bboxes, qualities) = pool
+        .run(move || {
+            let img = image.decode()?;
+            let t1 = std::time::Instant::now();
+            let bboxes =
+                detect_with_params(choice, &yolo_model, &None, &yolo_queue, &img, &params)?;
+            println!("inference time {:?}", t1.elapsed());
+            let qualities = assess_faces(&img, &bboxes, &quality_config, with_quality)?;
+            Ok((img, bboxes, qualities))
+        })
+        .await?;
+    Ok(face_detection_response(&bboxes, qualities, &img, &version))
 }
 
 #[utoipa::path(
     context_path="",
-    request_body(content = GetFaceRequest, content_type="multipart/form-data"),
+    params(ResponseVersionQuery),
+    request_body(content = GetFaceRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
     responses(
-        (status=200, description="returns face detections using `retinaface_10g` model", body=GetFaceResponse),
+        (status=200, description="returns face detections using `retinaface_10g` model, `?version=2` returns `GetFaceResponseV2`", body=GetFaceResponse),
+        (status=400, description="the image is corrupt, the detection parameters are invalid or the requested detector is not loaded", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
     )
 )]
 #[post("/get_face/retina")]
 pub async fn get_face_bbox_retinaface(
-    loaded_model: web::Data<GetFaceRetinaface>,
-    form: MultipartForm<GetFaceRequest>,
+    loaded_model: web::Data<FaceExtractor>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    quality_config: web::Data<QualityConfig>,
+    request: ImageRequest<GetFaceRequest>,
+    version: web::Query<ResponseVersionQuery>,
     req: HttpRequest,
-) -> actix_web::Result<HttpResponse> {
-    let get_face_req = form.into_inner();
-    let temp_file = get_face_req.input;
-    let img = tempfile_to_dynimg(temp_file)?;
-    let bboxes = loaded_model.forward(&img, 0.1).unwrap();
-    let mut _res = match bboxes {
-        InferenceResult::FaceDetection(ayylmao) => ayylmao,
-        _ => unreachable!(),
-    };
-    if _res.len() > 0 {
-        let _a = sort_conf_bbox(&mut _res);
-        Ok(HttpResponse::Ok().json(GetFaceResponse {
-            data: FaceResponse::from_bbox_vec(&_a),
-            message: String::from("success"),
-        }))
-    } else {
-        Ok(HttpResponse::Ok().json(GetFaceResponseNone {
-            message: String::from("no detections were found, please try with a better image!"),
-        }))
-    }
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.1)
+        .merge(&request.fields)
+        .map_err(FaceApiError::InvalidRequest)?;
+    let with_quality = request.fields.wants_quality() && version.is_v2();
+    let image = ingest.fetch(request.image).await?;
+    let (img, bboxes, qualities) = pool
+        .run(move || {
+            let img = image.decode()?;
+            let retina_model = &loaded_model.retina_model;
+            let bboxes = retina_model.detect(
+                &img,
+                params.confidence,
+                params.iou.unwrap_or(retina_model.nms_threshold),
+            )?;
+            let bboxes = params.filter(bboxes);
+            let qualities = assess_faces(&img, &bboxes, &quality_config, with_quality)?;
+            Ok((img, bboxes, qualities))
+        })
+        .await?;
+    Ok(face_detection_response(&bboxes, qualities, &img, &version))
 }
 
 #[utoipa::path(
     context_path="",
-    request_body(content = GetFaceRequest, content_type="multipart/form-data"),
+    params(ResponseVersionQuery),
+    request_body(content = GetFaceRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
     responses(
-        (status=200, description="returns face detections with highest confidence using `retinaface_10g` and `yolov8` model", body=GetFaceResponse),
+        (status=200, description="returns face detections with highest confidence using `retinaface_10g` and `yolov8` model, `?version=2` returns `GetFaceResponseV2`", body=GetFaceResponse),
+        (status=400, description="the image is corrupt, the detection parameters are invalid or the requested detector is not loaded", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
     )
 )]
 #[post("/get_face")]
 pub async fn extract_face(
     loaded_model: web::Data<FaceExtractor>,
-    form: MultipartForm<GetFaceRequest>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    quality_config: web::Data<QualityConfig>,
+    request: ImageRequest<GetFaceRequest>,
+    version: web::Query<ResponseVersionQuery>,
     req: HttpRequest,
-) -> actix_web::Result<HttpResponse> {
-    let get_face_req = form.into_inner();
-    let temp_file = get_face_req.input;
-    let img = tempfile_to_dynimg(temp_file)?;
-    let t1 = std::time::Instant::now();
-    let bboxes = loaded_model.extract_face_from_image(&img, 0.1);
-    println!("inference time {:?}", t1.elapsed());
-    let _res = match bboxes {
-        Ok(results) => results,
-        Err(_) => {
-           vec![]
-        }
-    };
-    if _res.len() > 0 {
-        Ok(HttpResponse::Ok().json(GetFaceResponse {
-            data: FaceResponse::from_bbox_vec(&_res),
-            message: String::from("sucess"),
-        }))
-    } else {
-        Ok(HttpResponse::Ok().json(GetFaceResponseNone {
-            message: String::from("no detections were found, please try with a better image!"),
-        }))
-    }
+) -> Result<HttpResponse, FaceApiError> {
+    // the highest confidence face unless `max_faces` asks for more
+    let params = DetectionParams::with_confidence(0.1).with_max_faces(1)
+        .merge(&request.fields)
+        .map_err(FaceApiError::InvalidRequest)?;
+    let with_quality = request.fields.wants_quality() && version.is_v2();
+    let face_extractor = Some(loaded_model);
+    let choice = resolve_detector(&None, &face_extractor, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    let (img, bboxes, qualities) = pool
+        .run(move || {
+            let img = image.decode()?;
+            let t1 = std::time::Instant::now();
+            let bboxes =
+                detect_with_params(choice, &None, &face_extractor, &yolo_queue, &img, &params)?;
+            println!("inference time {:?}", t1.elapsed());
+            let qualities = assess_faces(&img, &bboxes, &quality_config, with_quality)?;
+            Ok((img, bboxes, qualities))
+        })
+        .await?;
+    Ok(face_detection_response(&bboxes, qualities, &img, &version))
 }
 
 #[post("/get_largest_face")]
 pub async fn get_largest_face(
-    loaded_model: web::Data<GetFaceYolo>,
-    form: MultipartForm<GetLargestFaceRequest>,
+    yolo_model: Option<web::Data<GetFaceYolo>>,
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    request: ImageRequest<GetLargestFaceRequest>,
+    version: web::Query<ResponseVersionQuery>,
     _: HttpRequest,
-) -> actix_web::Result<HttpResponse> {
-    let get_face_req = form.into_inner();
-    let temp_file = get_face_req.input;
-    let img = tempfile_to_dynimg(temp_file)?;
-    let t1 = std::time::Instant::now();
-    let bboxes = loaded_model.forward(&img, 0.5).unwrap();
-    println!("inference time {:?}", t1.elapsed());
-
-    let mut _res = match bboxes {
-        InferenceResult::FaceDetection(ayylmao) => ayylmao,
-        _ => unreachable!(),
-    };
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.5)
+        .merge(&request.fields.without_max_faces())
+        .map_err(FaceApiError::InvalidRequest)?;
+    let choice = resolve_detector(&yolo_model, &face_extractor, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    // `None` when no face was detected
+    let largest = pool
+        .run(move || {
+            let img = image.decode()?;
+            let t1 = std::time::Instant::now();
+            let mut _res = detect_with_params(
+                choice,
+                &yolo_model,
+                &face_extractor,
+                &yolo_queue,
+                &img,
+                &params,
+            )?;
+            println!("inference time {:?}", t1.elapsed());
+            if _res.is_empty() {
+                return Ok(None);
+            }
+            let _a = sort_conf_bbox(&mut _res);
+            // if more than 1 bbox select the biggest
+            let biggest_bbox = match _a.len() > 1 {
+                true => get_largest_bbox(_a),
+                false => _a[0].to_owned(),
+            };
+            let largest_face = biggest_bbox.crop_bbox(&img)?;
+            let base64_image = image_to_base64(&largest_face)
+                .map_err(|e| FaceApiError::Internal(e.to_string()))?;
+            let coords_v1 = FaceResponse::from_bbox(&biggest_bbox);
+            let coords_v2 = FaceResponseV2::from_bbox(&biggest_bbox, img.width(), img.height());
+            Ok(Some((coords_v1, coords_v2, base64_image)))
+        })
+        .await?;
 
-    if !_res.is_empty() {
-        let mut _a = sort_conf_bbox(&mut _res);
-        let largest_face: DynamicImage;
-
-        // if more than 1 bbox select the biggest
-        // println!("_res {:?}", &_res);
-        if _a.len() > 1 {
-            let biggest_bbox = get_largest_bbox(_a);
-            largest_face = biggest_bbox.to_owned().crop_bbox(&img).unwrap();
-        } else {
-            largest_face = _a[0].to_owned().crop_bbox(&img).unwrap();
+    match largest {
+        Some((coords_v1, coords_v2, base64_image)) => {
+            if version.is_v2() {
+                return Ok(HttpResponse::Ok()
+                    .json(GetLargestFaceResponseV2::new(coords_v2, base64_image)));
+            }
+            Ok(HttpResponse::Ok()
+                .content_type("multipart/mixed; boundary=boundary")
+                .json(GetLargestFaceResponse::new(coords_v1, base64_image)))
         }
-        let base64_image = image_to_base64(&largest_face)?;
-        Ok(HttpResponse::Ok()
-            .content_type("multipart/mixed; boundary=boundary")
-            .json(GetLargestFaceResponse::new(
-                FaceResponse::from_bbox_vec(&_res)[0], // just copy the damn thing
-                base64_image,
-            )))
-    } else {
-        Ok(HttpResponse::Ok().json(GetFaceResponseNone {
+        None => Ok(HttpResponse::Ok().json(GetFaceResponseNone {
             message: String::from("no detections were found, please try with a better image!"),
-        }))
+        })),
     }
 }
+
+/// scores the largest face for enrollment: sharpness, brightness, size and,
+/// with a keypoint detector, yaw / pitch. `passed` applies the server thresholds
+#[utoipa::path(
+    context_path="",
+    request_body(content = GetLargestFaceRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
+    responses(
+        (status=200, description="returns the largest face and its quality report", body=FaceQualityResponse),
+        (status=400, description="no face was detected (`no_face`), the image is corrupt or the detection parameters are invalid", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
+    )
+)]
+#[post("/face_quality")]
+pub async fn get_face_quality(
+    yolo_model: Option<web::Data<GetFaceYolo>>,
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    quality_config: web::Data<QualityConfig>,
+    request: ImageRequest<GetLargestFaceRequest>,
+    _: HttpRequest,
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.5)
+        .merge(&request.fields.without_max_faces())
+        .map_err(FaceApiError::InvalidRequest)?;
+    let choice = resolve_detector(&yolo_model, &face_extractor, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    let response = pool
+        .run(move || {
+            let img = image.decode()?;
+            let bboxes = detect_with_params(
+                choice,
+                &yolo_model,
+                &face_extractor,
+                &yolo_queue,
+                &img,
+                &params,
+            )?;
+            if bboxes.is_empty() {
+                return Err(FaceApiError::NoFace);
+            }
+            let largest = get_largest_bbox(bboxes);
+            let quality = assess_face(&img, &largest, &quality_config)?;
+            Ok(FaceQualityResponse {
+                bbox: FaceResponseV2::from_bbox(&largest, img.width(), img.height()),
+                message: match quality.passed {
+                    true => String::from("success"),
+                    false => format!("face quality too low: {}", quality.failures.join(", ")),
+                },
+                quality,
+            })
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// 1:1 face verification, the largest face of each image is detected, aligned and
+/// embedded with `ArcFace` (in one batch) and the two vectors are compared.
+/// the second image can be replaced by a stored vector (`second_embedding`, json only)
+#[utoipa::path(
+    context_path="",
+    request_body(content = VerifyRequest, content_type="multipart/form-data", description="multipart form, or a json body (`VerifyJsonRequest`) with `*_base64` / `*_url` per image or `second_embedding`"),
+    responses(
+        (status=200, description="returns the cosine similarity and whether it reaches the match threshold", body=VerifyResponse),
+        (status=400, description="no face was detected in one of the images (`no_face`), an image is corrupt or the parameters are invalid", body=ErrorResponse),
+        (status=413, description="an upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="an upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
+    )
+)]
+#[post("/verify")]
+pub async fn verify_faces(
+    loaded_model: web::Data<GetFaceVecArcFace>,
+    yolo_model: Option<web::Data<GetFaceYolo>>,
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    arcface_queue: Option<web::Data<EmbeddingQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    verify_config: web::Data<VerifyConfig>,
+    request: ImagePairRequest,
+    _: HttpRequest,
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.5)
+        .merge(&request.fields.without_max_faces())
+        .map_err(FaceApiError::InvalidRequest)?;
+    let threshold = request.threshold.unwrap_or(verify_config.threshold);
+    if !(-1.0..=1.0).contains(&threshold) {
+        return Err(FaceApiError::InvalidRequest(format!(
+            "`threshold` must be between -1 and 1, got {}",
+            threshold
+        )));
+    }
+    let is_aligned = request.fields.is_aligned();
+    let choice = resolve_detector(&yolo_model, &face_extractor, &params)?;
+    let first = ingest.fetch(request.first).await?;
+    let (second, second_embedding) = match request.second {
+        VerifyTarget::Image(source) => (Some(ingest.fetch(source).await?), None),
+        VerifyTarget::Embedding(embedding) => (None, Some(embedding)),
+    };
+    let response = pool
+        .run(move || {
+            let mut images = vec![first.decode()?];
+            if let Some(second) = second {
+                images.push(second.decode()?);
+            }
+            let t1 = std::time::Instant::now();
+            let mut faces = Vec::with_capacity(images.len());
+            let mut bboxes = Vec::with_capacity(images.len());
+            for img in images {
+                match is_aligned {
+                    true => faces.push(img),
+                    false => {
+                        let (face, bbox) = largest_aligned_face(
+                            choice,
+                            &yolo_model,
+                            &face_extractor,
+                            &yolo_queue,
+                            &img,
+                            &params,
+                        )?;
+                        faces.push(face);
+                        bboxes.push(bbox);
+                    }
+                }
+            }
+            let mut embeddings = embed_faces(&loaded_model, &arcface_queue, faces)?.into_iter();
+            println!("inference time {:?}", t1.elapsed());
+            let missing = || FaceApiError::Internal(String::from("arcface returned no vector"));
+            let first_vec = embeddings.next().ok_or_else(missing)?;
+            let second_vec = match second_embedding {
+                Some(embedding) => embedding,
+                None => embeddings.next().ok_or_else(missing)?,
+            };
+            let similarity = cosine_similarity(&first_vec, &second_vec).ok_or_else(|| {
+                FaceApiError::InvalidRequest(format!(
+                    "`second_embedding` must be a non zero vector of {} values",
+                    first_vec.len()
+                ))
+            })?;
+            let mut bboxes = bboxes.into_iter();
+            Ok(VerifyResponse {
+                similarity,
+                threshold,
+                is_match: similarity >= threshold,
+                first: bboxes.next(),
+                second: bboxes.next(),
+                message: String::from("success"),
+            })
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// get one face vector per detected face with `ArcFace`,
+/// every face over `confidence` is aligned (or cropped if the detector
+/// has no keypoints) and embedded in a single batch
+#[utoipa::path(
+    context_path="",
+    request_body(content = GetFaceVecsRequest, content_type="multipart/form-data", description="multipart form, or a json body with `image_base64` / `url` and the same fields, or a raw `image/*` body with the fields in the query string"),
+    responses(
+        (status=200, description="returns the box, keypoints, confidence and `arcface` vector of every detected face", body=GetFaceVecsResponse),
+        (status=400, description="the image is corrupt, the detection parameters are invalid or the requested detector is not loaded", body=ErrorResponse),
+        (status=413, description="the upload is too large (`image_too_large`)", body=ErrorResponse),
+        (status=415, description="the upload is not a supported image (`unsupported_format`)", body=ErrorResponse),
+        (status=500, description="inference failed (`model_failure`)", body=ErrorResponse),
+        (status=503, description="too many requests are queued, retry after the `Retry-After` header", body=ErrorResponse),
+    )
+)]
+#[post("/get_vecs")]
+pub async fn get_face_vectors_multi(
+    loaded_model: web::Data<GetFaceVecArcFace>,
+    yolo_model: Option<web::Data<GetFaceYolo>>,
+    face_extractor: Option<web::Data<FaceExtractor>>,
+    yolo_queue: Option<web::Data<DetectionQueue>>,
+    arcface_queue: Option<web::Data<EmbeddingQueue>>,
+    pool: web::Data<BlockingPool>,
+    ingest: web::Data<ImageIngest>,
+    request: ImageRequest<GetFaceVecsRequest>,
+    _: HttpRequest,
+) -> Result<HttpResponse, FaceApiError> {
+    let params = DetectionParams::with_confidence(0.5)
+        .merge(&request.fields)
+        .map_err(FaceApiError::InvalidRequest)?;
+    let choice = resolve_detector(&yolo_model, &face_extractor, &params)?;
+    let image = ingest.fetch(request.image).await?;
+    let data = pool
+        .run(move || {
+            let img = image.decode()?;
+            let t1 = std::time::Instant::now();
+            let bboxes = detect_with_params(
+                choice,
+                &yolo_model,
+                &face_extractor,
+                &yolo_queue,
+                &img,
+                &params,
+            )?;
+            let faces = bboxes
+                .iter()
+                .map(|bbox| align_or_crop(&img, bbox))
+                .collect::<Result<Vec<DynamicImage>, FaceApiError>>()?;
+            let embeddings = embed_faces(&loaded_model, &arcface_queue, faces)?;
+            println!("inference time {:?}", t1.elapsed());
+            Ok(bboxes
+                .iter()
+                .zip(embeddings)
+                .map(|(bbox, embedding)| FaceEmbeddingResponse::new(bbox, embedding))
+                .collect::<Vec<FaceEmbeddingResponse>>())
+        })
+        .await?;
+    let message = match data.is_empty() {
+        true => String::from("no detections were found, please try with a better image!"),
+        false => String::from("success"),
+    };
+    Ok(HttpResponse::Ok().json(GetFaceVecsResponse { data, message }))
+}
@@ -0,0 +1,95 @@
+//! `ort` / `tract` parity of the batched face calls.
+//!
+//! `tract` is loaded for a batch of 1, so `detect_batch` / `forward_batch` take the
+//! one image at a time path, their results must match the batched `ort` run.
+//! the raw model outputs are compared in `soma_core::onnx_backend::tract_backend`.
+//! needs a photo with faces in `SOMA_TEST_IMAGE`, the models are read from
+//! `SOMA_YOLO_MODEL` / `SOMA_ARCFACE_MODEL` (default `./models/`).
+//! run with `cargo test --features smol -- --ignored`
+#![cfg(all(feature = "onnxruntime", feature = "smol"))]
+
+use image::DynamicImage;
+use soma_core::common_utils::Bbox;
+use soma_core::onnx_backend::{BackendConfig, BackendKind};
+use soma_face::config::{DEFAULT_ARCFACE_PATH, DEFAULT_YOLO_PATH};
+use soma_face::get_face::yolo::GetFaceYolo;
+use soma_face::get_face_vec::arcface::GetFaceVecArcFace;
+use soma_face::get_face_vec::cosine_similarity;
+
+fn existing_file(env: &str, default: &str) -> String {
+    let path = std::env::var(env).unwrap_or_else(|_| default.to_string());
+    assert!(
+        std::path::Path::new(&path).is_file(),
+        "nothing at `{}`, set `{}`",
+        path,
+        env
+    );
+    path
+}
+
+/// the photo plus a mirrored and a downscaled copy, so the batch images differ
+fn test_images() -> Vec<DynamicImage> {
+    let image = image::open(existing_file("SOMA_TEST_IMAGE", "")).unwrap();
+    let small = image.thumbnail(image.width() / 2, image.height() / 2);
+    vec![image.fliph(), small, image]
+}
+
+fn sorted(mut bboxes: Vec<Bbox>) -> Vec<Bbox> {
+    bboxes.sort_by(|a, b| a.x1.total_cmp(&b.x1));
+    bboxes
+}
+
+#[test]
+#[ignore = "needs models, set SOMA_*"]
+fn yolo_detect_batch_per_image_fallback() {
+    let path = existing_file("SOMA_YOLO_MODEL", DEFAULT_YOLO_PATH);
+    let images = test_images();
+    let load = |kind| {
+        GetFaceYolo::new_with_backend(&path, BackendConfig::new(kind), 640, 640, false).unwrap()
+    };
+    let ort = load(BackendKind::Ort);
+    let tract = load(BackendKind::Tract);
+    assert!(!tract.supports_batching());
+    let thresholds = vec![(0.5, 0.5); images.len()];
+    let ort_results = ort.detect_batch(&images, &thresholds).unwrap();
+    let tract_results = tract.detect_batch(&images, &thresholds).unwrap();
+    assert_eq!(ort_results.len(), images.len());
+    assert_eq!(tract_results.len(), images.len());
+    for (n, (a, b)) in ort_results.into_iter().zip(tract_results).enumerate() {
+        let (a, b) = (sorted(a), sorted(b));
+        assert!(!a.is_empty(), "no face found on image {}", n);
+        assert_eq!(a.len(), b.len(), "image {}", n);
+        for (a, b) in a.iter().zip(b.iter()) {
+            // pixels of the original image
+            for (x, y) in [(a.x1, b.x1), (a.y1, b.y1), (a.x2, b.x2), (a.y2, b.y2)] {
+                assert!((x - y).abs() < 1.0, "image {}: {:?} vs {:?}", n, a, b);
+            }
+            assert!((a.confidence - b.confidence).abs() < 1e-2, "image {}", n);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs models, set SOMA_*"]
+fn arcface_forward_batch_per_image_fallback() {
+    let path = existing_file("SOMA_ARCFACE_MODEL", DEFAULT_ARCFACE_PATH);
+    let images = test_images();
+    let load = |kind| GetFaceVecArcFace::new_with_backend(&path, BackendConfig::new(kind)).unwrap();
+    let ort = load(BackendKind::Ort);
+    let tract = load(BackendKind::Tract);
+    // a fixed batch of 1, what `supports_batching` looks at
+    assert_eq!(tract.onnx_model.input_dims().first(), Some(&1));
+    let ort_vectors = ort.forward_batch(&images).unwrap();
+    let tract_vectors = tract.forward_batch(&images).unwrap();
+    assert_eq!(ort_vectors.len(), images.len());
+    assert_eq!(tract_vectors.len(), images.len());
+    for (n, (a, b)) in ort_vectors.iter().zip(tract_vectors.iter()).enumerate() {
+        let similarity = cosine_similarity(a, b).unwrap();
+        assert!(
+            similarity > 0.999,
+            "image {}: cosine similarity {}",
+            n,
+            similarity
+        );
+    }
+}
@@ -0,0 +1,89 @@
+"""writes the SCRFD output fixtures used by the `get_face::retinaface` tests.
+
+every output is a dense tensor in model output order
+(`[scores * fmc, bboxes * fmc, (kps * fmc)]`), zero everywhere except the
+anchors listed in FIXTURES. distances and keypoints are in stride units,
+like the model emits them.
+
+    python3 make_fixtures.py
+"""
+
+import json
+import os
+
+# (stride, cell x, cell y, anchor, score, (l, t, r, b), keypoint offsets or None)
+FACE = [(-1.5, -1.0), (1.5, -1.0), (0.0, 0.5), (-1.0, 2.0), (1.0, 2.0)]
+STRONG = (8, 10, 10, 0, 0.9, (4, 4, 4, 4), FACE)
+# same face seen by stride 16, IoU 0.6 with STRONG
+SHIFTED = (16, 6, 5, 1, 0.8, (2, 2, 2, 2), None)
+LOW = (8, 30, 20, 0, 0.3, (2, 2, 2, 2), None)
+
+FIXTURES = {
+    "scrfd_6_640x640": dict(
+        width=640, height=640, fmc=3, anchors=2, kps=False, batched=False,
+        faces=[STRONG, SHIFTED, (32, 15, 15, 1, 0.7, (2, 2, 2, 2), None), LOW],
+    ),
+    "scrfd_9_320x256": dict(
+        width=320, height=256, fmc=3, anchors=2, kps=True, batched=True,
+        faces=[STRONG, SHIFTED, (32, 7, 5, 1, 0.7, (2, 2, 2, 2), FACE), LOW],
+    ),
+    "scrfd_10_256x256": dict(
+        width=256, height=256, fmc=5, anchors=1, kps=False, batched=False,
+        faces=[
+            STRONG,
+            SHIFTED[:3] + (0,) + SHIFTED[4:],
+            (64, 3, 3, 0, 0.7, (0.75, 0.75, 0.75, 0.75), None),
+            (128, 1, 1, 0, 0.6, (0.5, 0.5, 0.5, 0.5), None),
+            LOW,
+        ],
+    ),
+    "scrfd_15_384x256": dict(
+        width=384, height=256, fmc=5, anchors=1, kps=True, batched=True,
+        faces=[
+            STRONG,
+            SHIFTED[:3] + (0,) + SHIFTED[4:],
+            (64, 4, 2, 0, 0.7, (0.75, 0.75, 0.75, 0.75), FACE),
+            LOW,
+        ],
+    ),
+}
+
+
+def strides(fmc):
+    return [8, 16, 32] if fmc == 3 else [8, 16, 32, 64, 128]
+
+
+def tensor(rows, cols, values, batched):
+    shape = [1, rows, cols] if batched else [rows, cols]
+    # whole numbers as ints, the files are mostly zeros
+    data = [int(v) if v == int(v) else v for v in values]
+    return {"shape": shape, "data": data}
+
+
+def build(width, height, fmc, anchors, kps, batched, faces):
+    scores, bboxes, kpss = [], [], []
+    for stride in strides(fmc):
+        rows = (width // stride) * (height // stride) * anchors
+        score = [0.0] * rows
+        bbox = [0.0] * rows * 4
+        kp = [0.0] * rows * 10
+        for f_stride, x, y, anchor, conf, dist, offsets in faces:
+            if f_stride != stride:
+                continue
+            row = (y * (width // stride) + x) * anchors + anchor
+            score[row] = conf
+            bbox[row * 4:row * 4 + 4] = list(dist)
+            if offsets:
+                kp[row * 10:row * 10 + 10] = [v for point in offsets for v in point]
+        scores.append(tensor(rows, 1, score, batched))
+        bboxes.append(tensor(rows, 4, bbox, batched))
+        kpss.append(tensor(rows, 10, kp, batched))
+    outputs = scores + bboxes + (kpss if kps else [])
+    return {"input_width": width, "input_height": height, "outputs": outputs}
+
+
+if __name__ == "__main__":
+    here = os.path.dirname(os.path.abspath(__file__))
+    for name, spec in FIXTURES.items():
+        with open(os.path.join(here, name + ".json"), "w") as f:
+            json.dump(build(**spec), f, separators=(",", ":"))
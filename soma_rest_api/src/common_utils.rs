@@ -21,6 +21,14 @@ pub fn tempfile_to_dynimg(input_tempfile: TempFile) -> actix_web::Result<Dynamic
     Ok(img)
 }
 
+/// reads an uploaded file as base64, to forward it in a json body
+pub fn tempfile_to_base64(input_tempfile: TempFile) -> Result<String, Error> {
+    let mut file = input_tempfile.file;
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer)?;
+    Ok(general_purpose::STANDARD.encode(buffer))
+}
+
 pub fn base64_to_tempfile(base64_string: &str) -> Result<NamedTempFile, Error> {
     let mut temp_file = NamedTempFile::new()?;
     let image_bytes = base64_to_bytes(base64_string)?;
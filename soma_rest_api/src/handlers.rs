@@ -7,11 +7,30 @@ use serde::{Deserialize, Serialize};
 pub struct AddFaceRequest {
     pub input: TempFile,
     pub aligned: Text<bool>,
+    /// runs the face through `/face_quality` first and rejects it if it fails
+    pub check_quality: Option<Text<bool>>,
+    pub name: Option<Text<String>>,
+    /// 0 = male , 1 = female
+    pub gender: Option<Text<i64>>,
+    pub collection: Option<Text<String>>,
+    /// adds the face to this existing person, a new person is created when unset
+    pub person_uuid: Option<Text<String>>,
+}
+
+/// `input` is compared to a `second` image or to the enrolled face `face_uuid`,
+/// exactly one of them must be set
+#[derive(Debug, MultipartForm)]
+pub struct VerifyFaceRequest {
+    pub input: TempFile,
+    pub second: Option<TempFile>,
+    pub face_uuid: Option<Text<String>>,
+    pub aligned: Option<Text<bool>>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct AddFaceResponse {
     pub id: String,
+    pub person_uuid: String,
     pub message: String,
 }
 
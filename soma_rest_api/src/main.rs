@@ -9,7 +9,7 @@ use actix_web::{web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer};
 use dotenvy::dotenv;
 use env_logger;
 use lazy_static::lazy_static;
-use service::{add_face_vec, get_similar_faces_uuid, get_similar_faces_image};
+use service::{add_face_vec, get_similar_faces_uuid, get_similar_faces_image, verify_face};
 use std::env;
 
 lazy_static! {
@@ -21,15 +21,22 @@ lazy_static! {
         format!("{}/get_vec", addr)
     };
 
-    static ref GET_LARGEST_FACE_URL: String = {
+    static ref GET_FACE_URL: String = {
         dotenv().ok();
         let addr = env::var("FACE_API_ADDRESS").expect("api addr not found!");
-        format!("{}/get_largest_face", addr)
+        format!("{}/get_face", addr)
     };
-    static ref GET_FACE_URL: String = {
+
+    static ref GET_FACE_QUALITY_URL: String = {
         dotenv().ok();
         let addr = env::var("FACE_API_ADDRESS").expect("api addr not found!");
-        format!("{}/get_face", addr)
+        format!("{}/face_quality", addr)
+    };
+
+    static ref VERIFY_URL: String = {
+        dotenv().ok();
+        let addr = env::var("FACE_API_ADDRESS").expect("api addr not found!");
+        format!("{}/verify", addr)
     };
 
     static ref DB_API_POSTFACE_URL: String = {
@@ -38,6 +45,12 @@ lazy_static! {
         format!("{}/post_face_vec", addr)
     };
     
+    static ref DB_API_GET_FACE_UUID_URL: String = {
+        dotenv().ok();
+        let addr = env::var("DB_API_ADDRESS").expect("db api addr not found");
+        format!("{}/get_face_by_uuid", addr)
+    };
+
     static ref DB_API_SIMILAR_FACE_UUID_URL: String = {
         dotenv().ok();
         let addr = env::var("DB_API_ADDRESS").expect("db api addr not found");
@@ -63,6 +76,7 @@ async fn main() -> std::io::Result<()> {
         .service(add_face_vec)
         .service(get_similar_faces_uuid) 
         .service(get_similar_faces_image)
+        .service(verify_face)
         )
         .keep_alive(None)
         .bind("0.0.0.0:9995")?
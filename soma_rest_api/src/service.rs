@@ -1,26 +1,30 @@
 use crate::common_utils::print_splash;
-use crate::common_utils::{base64_to_bytes, base64_to_tempfile, tempfile_to_dynimg};
-use crate::handlers::{AddFaceRequest, AddFaceResponse};
-use crate::{DB_API_POSTFACE_URL, DB_API_SIMILAR_FACE_UUID_URL, GET_FACE_URL, GET_FACE_VEC_URL, GET_LARGEST_FACE_URL, DB_API_SIMILAR_FACE_IMAGE_URL};
+use crate::common_utils::{base64_to_bytes, base64_to_tempfile, tempfile_to_base64, tempfile_to_dynimg};
+use crate::handlers::{AddFaceRequest, AddFaceResponse, VerifyFaceRequest};
+use crate::{DB_API_GET_FACE_UUID_URL, DB_API_POSTFACE_URL, DB_API_SIMILAR_FACE_UUID_URL, GET_FACE_QUALITY_URL, GET_FACE_URL, GET_FACE_VEC_URL, DB_API_SIMILAR_FACE_IMAGE_URL, VERIFY_URL};
 use actix_multipart::form::MultipartForm;
 use actix_web;
 use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::rt::spawn;
 use actix_web::{get, post, web, HttpRequest, HttpResponse};
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Error, Result};
 use reqwest::blocking::multipart;
 use reqwest::{Client, ClientBuilder, Response};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use soma_db_api::handlers::GenericResponse;
 use tempfile::NamedTempFile;
 use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
 // im not writing the structs once more =))
-use soma_db_api::handlers::face::{ GetSimilarFacesByUuidRequest,  GetSimilarFacesByUuidResponse, InsertFaceRequest, GetSimilarFacesByEmbeddingRequest, GetSimilarFaceByImageRequest};
+use soma_db_api::handlers::face::{ FaceSearchFilter, SearchTuning, GetFaceByUuidRequest, GetFaceDetailResponse, GetSimilarFacesByUuidRequest,  GetSimilarFacesByUuidResponse, InsertFaceRequest, InsertFaceResponse, GetSimilarFacesByEmbeddingRequest, GetSimilarFaceByImageRequest};
 use soma_face::webserver::handler::{
-    GetFaceResponse, GetFaceResponseNone, GetFaceVecResponse, GetLargestFaceResponse,
+    FaceQualityResponse, GetFaceResponse, GetFaceResponseNone, GetFaceVecResponse,
+    GetLargestFaceResponse,
 };
+use std::path::Path;
 
 #[get("/")]
 async fn index() -> HttpResponse {
@@ -30,8 +34,8 @@ async fn index() -> HttpResponse {
         .body("haro")
 }
 
-fn get_face_vec_from_named_temp_file(
-    input_file: NamedTempFile,
+fn get_face_vec_from_tempfile(
+    input_tempfile: actix_multipart::form::tempfile::TempFile,
     client: reqwest::blocking::Client,
     aligned: bool,
 ) -> Result<GetFaceVecResponse> {
@@ -41,40 +45,36 @@ fn get_face_vec_from_named_temp_file(
     };
     let form = multipart::Form::new()
         .text("aligned", text_str)
-        .file("input", input_file)
+        .file("input", input_tempfile.file)
         .unwrap();
     let response = client
         .post(GET_FACE_VEC_URL.clone())
         .multipart(form)
-        .send()
-        .unwrap();
-    let lmao = match response.json() {
-        Ok(resp) => resp,
-        Err(e) => unreachable!(),
-    };
+        .send()?;
+    if !response.status().is_success() {
+        let none: GetFaceResponseNone = response.json()?;
+        return Err(anyhow!(none.message));
+    }
+    let lmao: GetFaceVecResponse = response.json()?;
     Ok(lmao)
 }
 
-fn get_face_vec_from_tempfile(
-    input_tempfile: actix_multipart::form::tempfile::TempFile,
-    client: reqwest::blocking::Client,
-    aligned: bool,
-) -> Result<GetFaceVecResponse> {
-    let text_str = match aligned {
-        true => String::from("true"),
-        _ => String::from("false"),
-    };
-    let form = multipart::Form::new()
-        .text("aligned", text_str)
-        .file("input", input_tempfile.file)
-        .unwrap();
+/// scores the largest face with `/face_quality`,
+/// errors if no face was found or the face api failed
+fn get_face_quality_from_path(
+    input_path: &Path,
+    client: &reqwest::blocking::Client,
+) -> Result<FaceQualityResponse> {
+    let form = multipart::Form::new().file("input", input_path)?;
     let response = client
-        .post(GET_FACE_VEC_URL.clone())
+        .post(GET_FACE_QUALITY_URL.clone())
         .multipart(form)
-        .send()
-        .unwrap();
-    let lmao: GetFaceVecResponse = response.json().unwrap();
-    Ok(lmao)
+        .send()?;
+    if !response.status().is_success() {
+        let none: GetFaceResponseNone = response.json()?;
+        return Err(anyhow!(none.message));
+    }
+    Ok(response.json()?)
 }
 
 fn get_face_from_tempfile(
@@ -93,22 +93,6 @@ fn get_face_from_tempfile(
     Ok(lmao)
 }
 
-fn get_biggest_face_from_tempfile(
-    input_tempfile: actix_multipart::form::tempfile::TempFile,
-    client: reqwest::blocking::Client,
-) -> Result<GetLargestFaceResponse> {
-    let form = multipart::Form::new()
-        .file("input", input_tempfile.file)
-        .unwrap();
-    let response = client
-        .post(GET_LARGEST_FACE_URL.clone())
-        .multipart(form)
-        .send()
-        .unwrap();
-    let lmao: GetLargestFaceResponse = response.json().unwrap();
-    Ok(lmao)
-}
-
 #[post("/get_similar_faces_uuid")]
 pub async fn get_similar_faces_uuid(
     form: web::Json<GetSimilarFacesByUuidRequest>,
@@ -139,59 +123,34 @@ pub async fn get_similar_faces_image(
     println!("IS ALIGNED {:?}", &align);
     let instance_uuid = String::from(Uuid::new_v4());
     let db_client = reqwest::Client::new();
-    let _resp: Response = match align {
-        false => {
-            // creates a bloking client to get faces
-            // idk how else we can do to send multipart
-            let largest_face = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                let biggest_face = get_biggest_face_from_tempfile(temp_file, client).unwrap();
-                biggest_face
-            })
-            .await
-            .unwrap();
-
-            let read_face = base64_to_tempfile(&largest_face.cropped_face).unwrap();
-
-            let face_vec: GetFaceVecResponse = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                get_face_vec_from_named_temp_file(read_face, client, true).unwrap()
-            })
-            .await
-            .unwrap();
-            let get_similar_face_by_image_request = GetSimilarFacesByEmbeddingRequest {
-                face_embedding: face_vec.data,
-                count: read_form.count.into_inner() as i64
-            }; 
-            let _resp = db_client
-            .post(DB_API_SIMILAR_FACE_IMAGE_URL.clone())
-                .json(&get_similar_face_by_image_request)
-                .send()
-                .await
-            .unwrap();
-                _resp
-        }
-        true => {
-            // just raw dogging the vec detections bro
-            let face_vec = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                get_face_vec_from_tempfile(temp_file, client, true).unwrap()
-            })
-            .await
-            .unwrap();
-            let get_similar_face_by_image_request = GetSimilarFacesByEmbeddingRequest {
-                face_embedding: face_vec.data,
-                count: read_form.count.into_inner() as i64
-            }; 
-            let _resp = db_client
-            .post(DB_API_SIMILAR_FACE_IMAGE_URL.clone())
-                .json(&get_similar_face_by_image_request)
-                .send()
-                .await
-            .unwrap();
-                _resp
+    // `/get_vec` detects and aligns the face itself when `aligned` is false
+    let face_vec = match spawn_blocking(move || {
+        let client = reqwest::blocking::Client::new();
+        get_face_vec_from_tempfile(temp_file, client, align)
+    })
+    .await
+    .unwrap()
+    {
+        Ok(face_vec) => face_vec,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(GenericResponse {
+                status: 400,
+                message: e.to_string(),
+            }))
         }
     };
+    let get_similar_face_by_image_request = GetSimilarFacesByEmbeddingRequest {
+        face_embedding: face_vec.data,
+        count: read_form.count.into_inner() as i64,
+        filter: FaceSearchFilter::default(),
+        tuning: SearchTuning::default(),
+    };
+    let _resp = db_client
+        .post(DB_API_SIMILAR_FACE_IMAGE_URL.clone())
+        .json(&get_similar_face_by_image_request)
+        .send()
+        .await
+        .unwrap();
     // TODO: change to proper response , am too sleepy
     if _resp.status().is_success() {
         let res: Vec<GetSimilarFacesByUuidResponse> = _resp.json().await.unwrap();
@@ -213,73 +172,144 @@ pub async fn add_face_vec(
     let read_form = form.into_inner();
     let temp_file = read_form.input;
     let align: bool = read_form.aligned.into_inner();
+    let check_quality = read_form
+        .check_quality
+        .map(|x| x.into_inner())
+        .unwrap_or(false);
     println!("IS ALIGNED {:?}", &align);
     let instance_uuid = String::from(Uuid::new_v4());
     let db_client = reqwest::Client::new();
-    let _resp: Response = match align {
-        false => {
-            // creates a bloking client to get faces
-            // idk how else we can do to send multipart
-            let largest_face = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                let biggest_face = get_biggest_face_from_tempfile(temp_file, client).unwrap();
-                biggest_face
-            })
-            .await
-            .unwrap();
-
-            let read_face = base64_to_tempfile(&largest_face.cropped_face).unwrap();
-
-            let face_vec: GetFaceVecResponse = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                get_face_vec_from_named_temp_file(read_face, client, true).unwrap()
-            })
-            .await
-            .unwrap();
-            // println!("face_vec {:?}", face_vec);
-            let insert_face_request = InsertFaceRequest::new(
-                face_vec.data,
-                Some("placeholder".to_string()),
-                Some(1),
-                String::from(&instance_uuid),
-            );
-            let _resp = db_client
-                .post(DB_API_POSTFACE_URL.clone())
-                .json(&insert_face_request)
-                .send()
-                .await
-                .unwrap();
-            _resp
+    // `/get_vec` detects and aligns the face itself when `aligned` is false
+    let face_vec = match spawn_blocking(move || {
+        let client = reqwest::blocking::Client::new();
+        // blurry, dark or turned faces make poor references, reject them before enrolling
+        if check_quality {
+            let report = get_face_quality_from_path(temp_file.file.path(), &client)?;
+            if !report.quality.passed {
+                return Err(anyhow!(report.message));
+            }
         }
-        true => {
-            // just raw dogging the vec detections bro
-            let face_vec = spawn_blocking(|| {
-                let client = reqwest::blocking::Client::new();
-                get_face_vec_from_tempfile(temp_file, client, true).unwrap()
-            })
-            .await
-            .unwrap();
-            let insert_face_request = InsertFaceRequest::new(
-                face_vec.data,
-                Some("placeholder".to_string()),
-                Some(1),
-                String::from(&instance_uuid),
-            );
-            let _resp = db_client
-                .post(DB_API_POSTFACE_URL.clone())
-                .json(&insert_face_request)
-                .send()
-                .await
-                .unwrap();
-            _resp
+        get_face_vec_from_tempfile(temp_file, client, align)
+    })
+    .await
+    .unwrap()
+    {
+        Ok(face_vec) => face_vec,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(GenericResponse {
+                status: 400,
+                message: e.to_string(),
+            }))
         }
     };
+    let insert_face_request = InsertFaceRequest {
+        embedding: face_vec.data,
+        name: read_form.name.map(|x| x.into_inner()),
+        gender: read_form.gender.map(|x| x.into_inner()),
+        face_uuid: String::from(&instance_uuid),
+        collection: read_form.collection.map(|x| x.into_inner()),
+        person_uuid: read_form.person_uuid.map(|x| x.into_inner()),
+    };
+    let _resp = db_client
+        .post(DB_API_POSTFACE_URL.clone())
+        .json(&insert_face_request)
+        .send()
+        .await
+        .unwrap();
     if _resp.status().is_success() {
+        let inserted: InsertFaceResponse = _resp.json().await.unwrap();
         Ok(HttpResponse::Ok().json(AddFaceResponse {
             id: instance_uuid,
+            person_uuid: inserted.person_uuid,
             message: String::from("success"),
         }))
+    } else if _resp.status().is_client_error() {
+        // e.g. an unknown person_uuid
+        let error: GenericResponse = _resp.json().await.unwrap();
+        Ok(HttpResponse::BadRequest().json(error))
     } else {
         Ok(HttpResponse::InternalServerError().into())
     }
-}
\ No newline at end of file
+}
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(GenericResponse {
+        status: 400,
+        message: message.to_string(),
+    })
+}
+
+/// 1:1 verification of `input` against a second image or an enrolled `face_uuid`,
+/// the comparison and the match threshold are `soma_face`'s `/verify`,
+/// its response (or error) is passed through as is
+#[post("/verify")]
+pub async fn verify_face(
+    form: MultipartForm<VerifyFaceRequest>,
+    _: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let read_form = form.into_inner();
+    let aligned = read_form.aligned.map(|x| x.into_inner()).unwrap_or(false);
+    let client = reqwest::Client::new();
+    let first_base64 = match tempfile_to_base64(read_form.input) {
+        Ok(data) => data,
+        Err(e) => return Ok(bad_request(&e.to_string())),
+    };
+    let mut body = json!({ "first_base64": first_base64, "aligned": aligned });
+    match (read_form.second, read_form.face_uuid) {
+        (Some(second), None) => match tempfile_to_base64(second) {
+            Ok(data) => body["second_base64"] = json!(data),
+            Err(e) => return Ok(bad_request(&e.to_string())),
+        },
+        (None, Some(face_uuid)) => {
+            let face_uuid = face_uuid.into_inner();
+            let stored = match client
+                .post(DB_API_GET_FACE_UUID_URL.clone())
+                .json(&GetFaceByUuidRequest {
+                    face_uuid: face_uuid.clone(),
+                })
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => response,
+                _ => {
+                    return Ok(HttpResponse::InternalServerError().json(GenericResponse {
+                        status: 500,
+                        message: String::from("there is an error getting the stored face"),
+                    }))
+                }
+            };
+            // the db api answers with a message instead of a list when the uuid is unknown
+            let faces: Vec<GetFaceDetailResponse> = stored.json().await.unwrap_or_default();
+            match faces.into_iter().next() {
+                Some(face) => body["second_embedding"] = json!(face.embedding),
+                None => {
+                    return Ok(HttpResponse::NotFound().json(GenericResponse {
+                        status: 404,
+                        message: format!("no face was found for face_uuid `{}`", face_uuid),
+                    }))
+                }
+            }
+        }
+        _ => return Ok(bad_request("send exactly one of `second` or `face_uuid`")),
+    }
+    let response = match client.post(VERIFY_URL.clone()).json(&body).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            return Ok(HttpResponse::BadGateway().json(GenericResponse {
+                status: 502,
+                message: format!("cannot reach the face api: {}", e),
+            }))
+        }
+    };
+    let status = StatusCode::from_u16(response.status().as_u16())
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    match response.bytes().await {
+        Ok(bytes) => Ok(HttpResponse::build(status)
+            .content_type(ContentType::json())
+            .body(bytes)),
+        Err(e) => Ok(HttpResponse::BadGateway().json(GenericResponse {
+            status: 502,
+            message: format!("cannot read the face api response: {}", e),
+        })),
+    }
+}